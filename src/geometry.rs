@@ -1,8 +1,9 @@
 use intersection::Intersection;
-use polygon::Polygon;
 use ray::Ray;
 use vector::Vector3D;
 
+pub mod bounding_box;
+pub mod frame;
 pub mod intersection;
 pub mod polygon;
 pub mod ray;
@@ -10,48 +11,248 @@ pub mod vector;
 
 pub static EPSILON: f64 = 1e-5;
 
-pub fn get_intersection(ray: &Ray, polygon: &Polygon) -> Option<Intersection> {
-    let first_edge = &polygon.second_point - &polygon.first_point;
-    let second_edge = &polygon.third_point - &polygon.first_point;
-    let h = ray.direction.cross(&second_edge);
-    let a = &h * &first_edge;
+/// Bound on the relative rounding error of `n` floating point operations.
+fn gamma(n: u32) -> f64 {
+    let n_epsilon = n as f64 * f64::EPSILON / 2.0;
+    n_epsilon / (1.0 - n_epsilon)
+}
 
-    if a > -EPSILON && a < EPSILON {
-        return None;
-    }
+/// Watertight ray-triangle test (Woop et al. 2013): triangles are moved into a space where
+/// the ray goes from the origin along +z, so the edges shared by triangles are tested
+/// exactly the same way from both sides and rays can't slip through them. Rays right
+/// through an edge or a vertex hit only one of the triangles sharing it.
+/// The transform is set up once per ray.
+pub struct ShearedRay<'a> {
+    ray: &'a Ray,
+    /// Direction component that becomes z, the next ones become x and y
+    z: usize,
+    shear: [f64; 2],
+    scale: f64,
+}
 
-    let f = 1.0 / a;
-    let s = &ray.from - &polygon.first_point;
-    let u = f * (&s * &h);
-    if !(0.0..=1.0).contains(&u) {
-        return None;
+impl<'a> ShearedRay<'a> {
+    pub fn new(ray: &'a Ray) -> Self {
+        // Largest direction component becomes z
+        let direction = &ray.direction;
+        let z = if direction.x.abs() > direction.y.abs() {
+            if direction.x.abs() > direction.z.abs() {
+                0
+            } else {
+                2
+            }
+        } else if direction.y.abs() > direction.z.abs() {
+            1
+        } else {
+            2
+        };
+        Self {
+            ray,
+            z,
+            shear: [
+                -direction[(z + 1) % 3] / direction[z],
+                -direction[(z + 2) % 3] / direction[z],
+            ],
+            scale: 1.0 / direction[z],
+        }
     }
-    let q = s.cross(&first_edge);
-    let v = f * (&ray.direction * &q);
-    if v < 0.0 || u + v > 1.0 {
-        return None;
-    }
-    let t = f * (&second_edge * &q);
 
-    if t < EPSILON {
-        return None;
+    pub fn direction(&self) -> &Vector3D {
+        &self.ray.direction
     }
 
-    let intersection_point = &ray.from + &ray.direction * t;
-    let unoriented_normal = polygon.weighted_normal(&intersection_point);
-    let normal = if &unoriented_normal * &ray.direction > 0.0 {
-        -unoriented_normal.normalize()
-    } else {
-        unoriented_normal.normalize()
-    };
-
-    Some(Intersection {
-        distance: (&intersection_point - &ray.from).len(),
-        position: intersection_point,
-        normal,
-    })
+    /// Hit of the triangle with the given corners. Its normal is the geometric one,
+    /// callers with vertex normals interpolate them.
+    pub fn intersect(&self, points: [&Vector3D; 3]) -> Option<Intersection> {
+        let ray = self.ray;
+        let mut transformed = points.map(|point| {
+            let [x, y, z] = match self.z {
+                0 => [
+                    point.y - ray.from.y,
+                    point.z - ray.from.z,
+                    point.x - ray.from.x,
+                ],
+                1 => [
+                    point.z - ray.from.z,
+                    point.x - ray.from.x,
+                    point.y - ray.from.y,
+                ],
+                _ => [
+                    point.x - ray.from.x,
+                    point.y - ray.from.y,
+                    point.z - ray.from.z,
+                ],
+            };
+            [x + self.shear[0] * z, y + self.shear[1] * z, z]
+        });
+        let [p0, p1, p2] = transformed;
+
+        // Edge functions, each the weight of the opposite vertex
+        let edges = [
+            p1[0] * p2[1] - p1[1] * p2[0],
+            p2[0] * p0[1] - p2[1] * p0[0],
+            p0[0] * p1[1] - p0[1] * p1[0],
+        ];
+        // Rays on an edge count as on the side they'd be on if moved slightly along x, or along y
+        // for edges along x, which is the same for both triangles sharing it
+        let sides = [0, 1, 2].map(|i| {
+            let (start, end) = (transformed[(i + 1) % 3], transformed[(i + 2) % 3]);
+            [edges[i], start[1] - end[1], end[0] - start[0]]
+                .into_iter()
+                .find(|value| *value != 0.0)
+                .map_or(0.0, f64::signum)
+        });
+        if sides.contains(&-1.0) && sides.contains(&1.0) {
+            return None;
+        }
+        let [e0, e1, e2] = edges;
+        let determinant = e0 + e1 + e2;
+        if determinant == 0.0 {
+            return None;
+        }
+        for point in transformed.iter_mut() {
+            point[2] *= self.scale;
+        }
+        let [p0, p1, p2] = transformed;
+        let scaled_distance = e0 * p0[2] + e1 * p1[2] + e2 * p2[2];
+        if scaled_distance * determinant <= 0.0 {
+            return None;
+        }
+        let t = scaled_distance / determinant;
+
+        // Hits closer than the rounding error of t may be the surface the ray starts on
+        let max_component = |i: usize| {
+            transformed
+                .iter()
+                .map(|point| point[i].abs())
+                .fold(0.0, f64::max)
+        };
+        let (max_x, max_y, max_z) = (max_component(0), max_component(1), max_component(2));
+        let delta_x = gamma(5) * (max_x + max_z);
+        let delta_y = gamma(5) * (max_y + max_z);
+        let delta_z = gamma(3) * max_z;
+        let delta_edge = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let max_edge = edges.iter().map(|edge| edge.abs()).fold(0.0, f64::max);
+        let delta_t = 3.0 * (gamma(3) * max_edge * max_z + delta_edge * max_z + delta_z * max_edge)
+            / determinant.abs();
+        if t <= delta_t {
+            return None;
+        }
+
+        let weights = edges.map(|edge| edge / determinant);
+        let intersection_point =
+            points[0] * weights[0] + points[1] * weights[1] + points[2] * weights[2];
+        let error = (points[0] * weights[0]).abs()
+            + (points[1] * weights[1]).abs()
+            + (points[2] * weights[2]).abs();
+
+        let geometric_normal = (points[1] - points[0])
+            .cross(points[2] - points[0])
+            .normalize();
+        let front_facing = &geometric_normal * &ray.direction < 0.0;
+        let geometric_normal = if front_facing {
+            geometric_normal
+        } else {
+            -geometric_normal
+        };
+
+        Some(Intersection {
+            distance: (&intersection_point - &ray.from).len(),
+            position: intersection_point,
+            error: error * gamma(7),
+            normal: geometric_normal.clone(),
+            geometric_normal,
+            barycentric: [weights[1], weights[2]],
+            front_facing,
+        })
+    }
 }
 
 pub fn reflect(direction_in: &Vector3D, normal: &Vector3D) -> Vector3D {
     direction_in - normal * 2.0 * (normal * direction_in)
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn hits(triangles: &[[Vector3D; 3]], from: &Vector3D, to: &Vector3D) -> usize {
+        let ray = Ray {
+            from: from.clone(),
+            direction: (to - from).normalize(),
+            inside: false,
+            differential: None,
+        };
+        let sheared_ray = ShearedRay::new(&ray);
+        triangles
+            .iter()
+            .filter(|[a, b, c]| sheared_ray.intersect([a, b, c]).is_some())
+            .count()
+    }
+
+    /// Origins all around the z = 0 plane, but not in it.
+    fn origins() -> impl Iterator<Item = Vector3D> {
+        let mut rng = rand::thread_rng();
+        let axes = [
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+            [0.3, 0.0, 2.0],
+            [0.0, -0.5, 1.0],
+        ];
+        let random = (0..1000).map(move |_| {
+            let z = rng.gen_range(0.1..2.0) * if rng.gen() { 1.0 } else { -1.0 };
+            [rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0), z]
+        });
+        axes.into_iter().chain(random).map(Vector3D::from)
+    }
+
+    #[test]
+    fn rays_through_a_shared_edge_hit_one_triangle() {
+        let [a, b, c, d] = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, -1.0, 0.0],
+        ]
+        .map(Vector3D::from);
+        let consistent = [
+            [a.clone(), b.clone(), c.clone()],
+            [b.clone(), a.clone(), d.clone()],
+        ];
+        let inconsistent = [[a.clone(), b.clone(), c], [a.clone(), b.clone(), d]];
+        for triangles in [consistent, inconsistent] {
+            for t in [0.25, 0.5, 0.7] {
+                let target = &a * (1.0 - t) + &b * t;
+                for from in origins() {
+                    assert_eq!(hits(&triangles, &from, &target), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rays_through_a_shared_vertex_hit_one_triangle() {
+        let center = Vector3D::from([0.0, 0.0, 0.0]);
+        let ring: Vec<Vector3D> = (0..7)
+            .map(|i| {
+                let angle = i as f64 / 7.0 * 2.0 * std::f64::consts::PI;
+                Vector3D::from([angle.cos(), angle.sin(), 0.0])
+            })
+            .collect();
+        let fan: Vec<[Vector3D; 3]> = (0..ring.len())
+            .map(|i| {
+                let (first, second) = (ring[i].clone(), ring[(i + 1) % ring.len()].clone());
+                // Some triangles wound the other way
+                if i % 3 == 0 {
+                    [center.clone(), second, first]
+                } else {
+                    [center.clone(), first, second]
+                }
+            })
+            .collect();
+        for from in origins() {
+            assert_eq!(hits(&fan, &from, &center), 1);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use super::ray::Ray;
+use super::vector::Vector3D;
+
+/// Axis aligned box.
+#[derive(Clone)]
+pub struct BoundingBox {
+    pub min: Vector3D,
+    pub max: Vector3D,
+}
+
+impl BoundingBox {
+    pub fn from_point(point: &Vector3D) -> Self {
+        Self {
+            min: point.clone(),
+            max: point.clone(),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> Self {
+        Self {
+            min: Vector3D {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Vector3D {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+
+    pub fn union_point(&self, point: &Vector3D) -> Self {
+        self.union(&BoundingBox::from_point(point))
+    }
+
+    pub fn diagonal(&self) -> Vector3D {
+        &self.max - &self.min
+    }
+
+    pub fn centroid(&self) -> Vector3D {
+        (&self.min + &self.max) / 2.0
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let diagonal = self.diagonal();
+        2.0 * (diagonal.x * diagonal.y + diagonal.y * diagonal.z + diagonal.z * diagonal.x)
+    }
+
+    pub fn contains(&self, point: &Vector3D) -> bool {
+        (0..3).all(|axis| self.min[axis] <= point[axis] && point[axis] <= self.max[axis])
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let diagonal = self.diagonal();
+        if diagonal.x >= diagonal.y && diagonal.x >= diagonal.z {
+            0
+        } else if diagonal.y >= diagonal.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Distances along the ray where it enters and leaves the box, if it goes through it.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut near = f64::NEG_INFINITY;
+        let mut far = f64::INFINITY;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.from[axis]) * inverse_direction;
+            let mut t1 = (self.max[axis] - ray.from[axis]) * inverse_direction;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN appears for rays parallel to the slab and starting on its border
+            if !t0.is_nan() {
+                near = near.max(t0);
+            }
+            if !t1.is_nan() {
+                far = far.min(t1);
+            }
+        }
+
+        if near <= far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use super::vector::Vector3D;
+
+/// Orthonormal shading frame, in which the normal is the z axis.
+pub struct Frame {
+    pub tangent: Vector3D,
+    pub bitangent: Vector3D,
+    pub normal: Vector3D,
+}
+
+impl Frame {
+    /// Branchless basis construction by Duff et al. 2017
+    pub fn from_normal(normal: &Vector3D) -> Self {
+        let sign = 1.0_f64.copysign(normal.z);
+        let a = -1.0 / (sign + normal.z);
+        let b = normal.x * normal.y * a;
+        Self {
+            tangent: Vector3D {
+                x: 1.0 + sign * normal.x * normal.x * a,
+                y: sign * b,
+                z: -sign * normal.x,
+            },
+            bitangent: Vector3D {
+                x: b,
+                y: sign + normal.y * normal.y * a,
+                z: -normal.y,
+            },
+            normal: normal.clone(),
+        }
+    }
+
+    pub fn to_local(&self, vector: &Vector3D) -> Vector3D {
+        Vector3D {
+            x: vector * &self.tangent,
+            y: vector * &self.bitangent,
+            z: vector * &self.normal,
+        }
+    }
+
+    pub fn to_world(&self, vector: &Vector3D) -> Vector3D {
+        &self.tangent * vector.x + &self.bitangent * vector.y + &self.normal * vector.z
+    }
+}
@@ -3,6 +3,46 @@ use super::vector::Vector3D;
 #[derive(PartialEq, Clone)]
 pub struct Intersection {
     pub position: Vector3D,
+    /// Bounds on the rounding error of the position components
+    pub error: Vector3D,
+    /// Shading normal, facing the ray origin
     pub normal: Vector3D,
+    /// Normal of the polygon plane, facing the ray origin
+    pub geometric_normal: Vector3D,
     pub distance: f64,
+    /// Weights of the second and third vertices of the polygon
+    pub barycentric: [f64; 2],
+    /// Whether the ray hit the front of the polygon, where its vertices go counterclockwise
+    pub front_facing: bool,
+}
+
+impl Intersection {
+    /// Origin of rays leaving the surface along `direction`: the position pushed along
+    /// the geometric normal past its rounding error, so the rays can't hit the surface again.
+    pub fn spawn_origin(&self, direction: &Vector3D) -> Vector3D {
+        let normal = &self.geometric_normal;
+        let distance = &normal.abs() * &self.error;
+        let offset = if direction * normal < 0.0 {
+            normal * -distance
+        } else {
+            normal * distance
+        };
+        let origin = &self.position + &offset;
+
+        // The sum may round back towards the surface
+        let away = |coordinate: f64, offset: f64| {
+            if offset > 0.0 {
+                coordinate.next_up()
+            } else if offset < 0.0 {
+                coordinate.next_down()
+            } else {
+                coordinate
+            }
+        };
+        Vector3D::from([
+            away(origin.x, offset.x),
+            away(origin.y, offset.y),
+            away(origin.z, offset.z),
+        ])
+    }
 }
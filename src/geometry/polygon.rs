@@ -1,29 +1,33 @@
 use super::vector::Vector3D;
 
-#[derive(PartialEq)]
-pub struct Polygon {
-    pub first_point: Vector3D,
-    pub second_point: Vector3D,
-    pub third_point: Vector3D,
-    pub first_normal: Vector3D,
-    pub second_normal: Vector3D,
-    pub third_normal: Vector3D,
-}
+/// Derivatives of the position along the texture coordinates over a triangle,
+/// none if its texture coordinates are degenerate.
+pub fn position_derivatives(
+    points: [&Vector3D; 3],
+    coordinates: &[[f64; 2]; 3],
+) -> Option<(Vector3D, Vector3D)> {
+    let first_edge = points[1] - points[0];
+    let second_edge = points[2] - points[0];
+    let [du1, dv1] = [
+        coordinates[1][0] - coordinates[0][0],
+        coordinates[1][1] - coordinates[0][1],
+    ];
+    let [du2, dv2] = [
+        coordinates[2][0] - coordinates[0][0],
+        coordinates[2][1] - coordinates[0][1],
+    ];
+    let determinant = du1 * dv2 - du2 * dv1;
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
 
-impl Polygon {
-    pub fn weighted_normal(&self, x: &Vector3D) -> Vector3D {
-        let area_abx = (&self.second_point - &self.first_point)
-            .cross(&(&self.second_point - x))
-            .len();
-        let area_bcx = (&self.third_point - &self.second_point)
-            .cross(&(&self.third_point - x))
-            .len();
-        let area_cax = (&self.third_point - &self.first_point)
-            .cross(&(&self.first_point - x))
-            .len();
+    Some((
+        (&first_edge * dv2 - &second_edge * dv1) / determinant,
+        (&second_edge * du1 - &first_edge * du2) / determinant,
+    ))
+}
 
-        &self.first_normal * area_bcx
-            + &self.second_normal * area_cax
-            + &self.third_normal * area_abx
-    }
+/// Value at the point with barycentric coordinates `[u, v]`, weights of the second and third vertices.
+pub fn interpolate(values: &[Vector3D; 3], [u, v]: [f64; 2]) -> Vector3D {
+    &values[0] * (1.0 - u - v) + &values[1] * u + &values[2] * v
 }
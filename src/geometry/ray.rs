@@ -1,3 +1,4 @@
+use super::intersection::Intersection;
 use super::vector::Vector3D;
 
 #[derive(PartialEq)]
@@ -5,11 +6,82 @@ pub struct Ray {
     pub from: Vector3D,
     pub direction: Vector3D,
     pub inside: bool,
+    /// How the ray changes between neighbouring pixels, if it is followed from the camera
+    pub differential: Option<RayDifferential>,
 }
 
 impl Ray {
-    pub fn propagate(mut self, distance: f64) -> Self {
-        self.from += &(&self.direction * distance);
-        self
+    /// Differential of the ray at its `intersection`, origins moved along the polygon there.
+    pub fn differential_at(&self, intersection: &Intersection) -> Option<RayDifferential> {
+        self.differential.as_ref()?.transferred(
+            &self.direction,
+            intersection.distance,
+            &intersection.geometric_normal,
+        )
+    }
+}
+
+/// Derivatives of the ray origin and direction with respect to the pixel x and y.
+#[derive(PartialEq, Clone)]
+pub struct RayDifferential {
+    pub origin: [Vector3D; 2],
+    pub direction: [Vector3D; 2],
+}
+
+impl RayDifferential {
+    /// Differentials of the point where the unit `direction` hits a plane with `normal`
+    /// at `distance`, the origins move along that plane.
+    fn transferred(&self, direction: &Vector3D, distance: f64, normal: &Vector3D) -> Option<Self> {
+        let cosine = normal * direction;
+        if cosine.abs() < 1e-9 {
+            return None;
+        }
+        let origin = [0, 1].map(|i| {
+            let offset = &self.origin[i] + &self.direction[i] * distance;
+            let along = -(normal * &offset) / cosine;
+            offset + direction * along
+        });
+        Some(Self {
+            origin,
+            direction: self.direction.clone(),
+        })
+    }
+
+    /// Differentials after a perfectly specular bounce of the unit `outgoing` direction, which
+    /// points back along the ray, to `incoming` about the shading `normal`.
+    /// The normal is taken as constant over the footprint.
+    pub fn specular(&self, outgoing: &Vector3D, incoming: &Vector3D, normal: &Vector3D) -> Self {
+        let normal = if outgoing * normal < 0.0 {
+            -normal
+        } else {
+            normal.clone()
+        };
+        let direction = if incoming * &normal > 0.0 {
+            self.direction
+                .clone()
+                .map(|derivative| &derivative - &normal * (2.0 * (&derivative * &normal)))
+        } else {
+            // Relative index of refraction from the ratio of the tangential components
+            let cos_outgoing = outgoing * &normal;
+            let cos_incoming = incoming * &normal;
+            let tangential =
+                |direction: &Vector3D, cosine: f64| (direction - &normal * cosine).len();
+            let sin_outgoing = tangential(outgoing, cos_outgoing);
+            let eta = if sin_outgoing > 1e-9 {
+                tangential(incoming, cos_incoming) / sin_outgoing
+            } else {
+                1.0
+            };
+            self.direction.clone().map(|derivative| {
+                // Derivatives of the outgoing direction are opposite to those of the ray
+                let d_cosine = -(&derivative * &normal);
+                let d_mu = (eta - eta * eta * cos_outgoing / cos_incoming) * d_cosine;
+                &derivative * eta + &normal * d_mu
+            })
+        };
+        Self {
+            origin: self.origin.clone(),
+            direction,
+        }
     }
 }
@@ -1,21 +1,23 @@
 use std::borrow::Borrow;
 
+use num_traits::Float;
 use serde::{Deserialize, Serialize};
 
+/// Vector of `f64` components unless told otherwise, `f32` ones halve the memory of bulky geometry.
 #[derive(Default, PartialEq, Deserialize, Serialize)]
-pub struct Vector3D {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+pub struct Vector3D<T = f64> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
 }
 
-impl Vector3D {
+impl<T: Float + Default> Vector3D<T> {
     pub fn new() -> Self {
         Default::default()
     }
 }
 
-impl Clone for Vector3D {
+impl<T: Copy> Clone for Vector3D<T> {
     fn clone(&self) -> Self {
         Self {
             x: self.x,
@@ -25,8 +27,8 @@ impl Clone for Vector3D {
     }
 }
 
-impl From<[f64; 3]> for Vector3D {
-    fn from(array: [f64; 3]) -> Self {
+impl<T: Copy> From<[T; 3]> for Vector3D<T> {
+    fn from(array: [T; 3]) -> Self {
         Self {
             x: array[0],
             y: array[1],
@@ -35,9 +37,22 @@ impl From<[f64; 3]> for Vector3D {
     }
 }
 
-impl<R> std::ops::Add<R> for Vector3D
+impl<T> std::ops::Index<usize> for Vector3D<T> {
+    type Output = T;
+
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector3D has no axis {}", axis),
+        }
+    }
+}
+
+impl<T: Float, R> std::ops::Add<R> for Vector3D<T>
 where
-    R: Borrow<Vector3D>,
+    R: Borrow<Vector3D<T>>,
 {
     type Output = Self;
 
@@ -50,11 +65,11 @@ where
     }
 }
 
-impl<R> std::ops::Add<R> for &Vector3D
+impl<T: Float, R> std::ops::Add<R> for &Vector3D<T>
 where
-    R: Borrow<Vector3D>,
+    R: Borrow<Vector3D<T>>,
 {
-    type Output = Vector3D;
+    type Output = Vector3D<T>;
 
     fn add(self, rhs: R) -> Self::Output {
         Self::Output {
@@ -65,19 +80,19 @@ where
     }
 }
 
-impl<R> std::ops::AddAssign<R> for Vector3D
+impl<T: Float, R> std::ops::AddAssign<R> for Vector3D<T>
 where
-    R: Borrow<Vector3D>,
+    R: Borrow<Vector3D<T>>,
 {
     fn add_assign(&mut self, rhs: R) {
-        self.x += rhs.borrow().x;
-        self.y += rhs.borrow().y;
-        self.z += rhs.borrow().z;
+        self.x = self.x + rhs.borrow().x;
+        self.y = self.y + rhs.borrow().y;
+        self.z = self.z + rhs.borrow().z;
     }
 }
 
-impl std::ops::Neg for Vector3D {
-    type Output = Vector3D;
+impl<T: Float> std::ops::Neg for Vector3D<T> {
+    type Output = Vector3D<T>;
 
     fn neg(self) -> Self::Output {
         Self {
@@ -88,8 +103,8 @@ impl std::ops::Neg for Vector3D {
     }
 }
 
-impl std::ops::Neg for &Vector3D {
-    type Output = Vector3D;
+impl<T: Float> std::ops::Neg for &Vector3D<T> {
+    type Output = Vector3D<T>;
 
     fn neg(self) -> Self::Output {
         Vector3D {
@@ -100,11 +115,11 @@ impl std::ops::Neg for &Vector3D {
     }
 }
 
-impl<R> std::ops::Sub<R> for Vector3D
+impl<T: Float, R> std::ops::Sub<R> for Vector3D<T>
 where
-    R: Borrow<Vector3D>,
+    R: Borrow<Vector3D<T>>,
 {
-    type Output = Vector3D;
+    type Output = Vector3D<T>;
 
     fn sub(self, rhs: R) -> Self::Output {
         Self::Output {
@@ -115,11 +130,11 @@ where
     }
 }
 
-impl<R> std::ops::Sub<R> for &Vector3D
+impl<T: Float, R> std::ops::Sub<R> for &Vector3D<T>
 where
-    R: Borrow<Vector3D>,
+    R: Borrow<Vector3D<T>>,
 {
-    type Output = Vector3D;
+    type Output = Vector3D<T>;
 
     fn sub(self, rhs: R) -> Self::Output {
         Self::Output {
@@ -130,29 +145,29 @@ where
     }
 }
 
-impl<R> std::ops::SubAssign<R> for Vector3D
+impl<T: Float, R> std::ops::SubAssign<R> for Vector3D<T>
 where
-    R: Borrow<Vector3D>,
+    R: Borrow<Vector3D<T>>,
 {
     fn sub_assign(&mut self, rhs: R) {
-        self.x -= rhs.borrow().x;
-        self.y -= rhs.borrow().y;
-        self.z -= rhs.borrow().z;
+        self.x = self.x - rhs.borrow().x;
+        self.y = self.y - rhs.borrow().y;
+        self.z = self.z - rhs.borrow().z;
     }
 }
 
-impl std::ops::Mul<&Vector3D> for &Vector3D {
-    type Output = f64;
+impl<T: Float> std::ops::Mul<&Vector3D<T>> for &Vector3D<T> {
+    type Output = T;
 
-    fn mul(self, rhs: &Vector3D) -> Self::Output {
+    fn mul(self, rhs: &Vector3D<T>) -> Self::Output {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 }
 
-impl std::ops::Mul<f64> for Vector3D {
-    type Output = Vector3D;
+impl<T: Float> std::ops::Mul<T> for Vector3D<T> {
+    type Output = Vector3D<T>;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: T) -> Self::Output {
         Self::Output {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -161,10 +176,10 @@ impl std::ops::Mul<f64> for Vector3D {
     }
 }
 
-impl std::ops::Mul<f64> for &Vector3D {
-    type Output = Vector3D;
+impl<T: Float> std::ops::Mul<T> for &Vector3D<T> {
+    type Output = Vector3D<T>;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: T) -> Self::Output {
         Self::Output {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -173,18 +188,30 @@ impl std::ops::Mul<f64> for &Vector3D {
     }
 }
 
-impl std::ops::MulAssign<f64> for Vector3D {
-    fn mul_assign(&mut self, rhs: f64) {
-        self.x *= rhs;
-        self.y *= rhs;
-        self.z *= rhs;
+impl<T: Float> std::ops::MulAssign<T> for Vector3D<T> {
+    fn mul_assign(&mut self, rhs: T) {
+        self.x = self.x * rhs;
+        self.y = self.y * rhs;
+        self.z = self.z * rhs;
+    }
+}
+
+impl<T: Float> std::ops::Div<T> for Vector3D<T> {
+    type Output = Vector3D<T>;
+
+    fn div(self, rhs: T) -> Self::Output {
+        Vector3D {
+            x: self.x / rhs,
+            y: self.y / rhs,
+            z: self.z / rhs,
+        }
     }
 }
 
-impl<'a> std::ops::Div<f64> for &'a Vector3D {
-    type Output = Vector3D;
+impl<T: Float> std::ops::Div<T> for &Vector3D<T> {
+    type Output = Vector3D<T>;
 
-    fn div(self, rhs: f64) -> Self::Output {
+    fn div(self, rhs: T) -> Self::Output {
         Vector3D {
             x: self.x / rhs,
             y: self.y / rhs,
@@ -193,20 +220,20 @@ impl<'a> std::ops::Div<f64> for &'a Vector3D {
     }
 }
 
-impl std::ops::DivAssign<f64> for Vector3D {
-    fn div_assign(&mut self, rhs: f64) {
-        self.x /= rhs;
-        self.y /= rhs;
-        self.z /= rhs;
+impl<T: Float> std::ops::DivAssign<T> for Vector3D<T> {
+    fn div_assign(&mut self, rhs: T) {
+        self.x = self.x / rhs;
+        self.y = self.y / rhs;
+        self.z = self.z / rhs;
     }
 }
 
-impl Vector3D {
-    pub fn f2_norm(&self) -> f64 {
+impl<T: Float> Vector3D<T> {
+    pub fn f2_norm(&self) -> T {
         self * self
     }
 
-    pub fn len(&self) -> f64 {
+    pub fn len(&self) -> T {
         self.f2_norm().sqrt()
     }
 
@@ -215,9 +242,31 @@ impl Vector3D {
         self
     }
 
+    pub fn abs(&self) -> Self {
+        Self::from([self.x.abs(), self.y.abs(), self.z.abs()])
+    }
+
+    pub fn component_mul<R>(&self, rhs: R) -> Self
+    where
+        R: Borrow<Vector3D<T>>,
+    {
+        Self {
+            x: self.x * rhs.borrow().x,
+            y: self.y * rhs.borrow().y,
+            z: self.z * rhs.borrow().z,
+        }
+    }
+
+    /// Rodrigues' rotation around the unit `axis` by `angle` radians.
+    pub fn rotate(&self, axis: &Vector3D<T>, angle: T) -> Self {
+        self * angle.cos()
+            + axis.cross(self) * angle.sin()
+            + axis * ((axis * self) * (T::one() - angle.cos()))
+    }
+
     pub fn cross<R>(&self, rhs: R) -> Self
     where
-        R: Borrow<Vector3D>,
+        R: Borrow<Vector3D<T>>,
     {
         Self {
             x: self.y * rhs.borrow().z - self.z * rhs.borrow().y,
@@ -225,4 +274,14 @@ impl Vector3D {
             z: self.x * rhs.borrow().y - self.y * rhs.borrow().x,
         }
     }
+
+    /// Same vector with components of another precision.
+    pub fn cast<U: Float>(&self) -> Vector3D<U> {
+        let cast = |component: T| U::from(component).unwrap();
+        Vector3D {
+            x: cast(self.x),
+            y: cast(self.y),
+            z: cast(self.z),
+        }
+    }
 }
@@ -2,7 +2,8 @@ use std::path::Path;
 
 use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
 
-use crate::raytracer::Raytracer;
+use crate::raytracer::aov::Aov;
+use crate::raytracer::{Raytracer, RenderOptions};
 
 mod geometry;
 mod raytracer;
@@ -28,6 +29,43 @@ pub fn init() -> Result<(), SetLoggerError> {
     log::set_boxed_logger(Box::new(SimpleLogger)).map(|()| log::set_max_level(LevelFilter::Info))
 }
 
+fn read_options(args: impl Iterator<Item = String>) -> RenderOptions {
+    let mut options = RenderOptions::default();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--aov" => {
+                let aovs = args.next().expect("No AOV list given");
+                options.aovs = if aovs == "all" {
+                    Aov::ALL.to_vec()
+                } else {
+                    aovs.split(',').map(|aov| aov.parse().unwrap()).collect()
+                };
+            }
+            "--denoise" => {
+                options.denoise = args.next().expect("No denoise mode given").parse().unwrap();
+            }
+            "--mis" => {
+                options.mis = args
+                    .next()
+                    .expect("No MIS heuristic given")
+                    .parse()
+                    .unwrap();
+            }
+            "--spectral" => options.spectral = true,
+            "--cull-back-faces" => options.cull_back_faces = true,
+            "--scene-cache" => {
+                options.scene_cache = Some(args.next().expect("No scene cache file given").into());
+            }
+            "--cache-bvh" => options.cache_bvh = true,
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+
+    options
+}
+
 fn main() {
     init().unwrap();
 
@@ -36,6 +74,8 @@ fn main() {
     let ray_caster_path = std::env::args().nth(2).expect("No ray caster path given");
     let ray_caster_path = Path::new(&ray_caster_path);
 
-    let mut raytracer = Raytracer::new(scene_path, ray_caster_path);
+    let options = read_options(std::env::args().skip(3));
+
+    let mut raytracer = Raytracer::new(scene_path, ray_caster_path, options);
     raytracer.raytrace();
 }
@@ -1,6 +1,6 @@
 use std::borrow::Borrow;
 use std::cmp::min;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use std::sync::{Arc, RwLock};
@@ -8,47 +8,114 @@ use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
-use image::RgbImage;
+use image::{Rgb32FImage, RgbImage};
 use indicatif::{ProgressBar, ProgressIterator};
 use log::info;
+use rand::{thread_rng, Rng};
 use threadpool::ThreadPool;
 
+use aov::{Aov, AovBuffers};
+use denoiser::DenoiseMode;
+use mis::MisHeuristic;
 use ray_caster::RayCaster;
 
+use crate::geometry::ray::Ray;
 use crate::geometry::vector::Vector3D;
-use crate::raytracer::illumination::{calculate_illumination, get_sky};
+use crate::raytracer::illumination::{
+    get_sky, trace_primary_rays, FirstHit, Illumination, PathContext,
+};
+use crate::scene::bvh::PACKET_SIZE;
+use crate::scene::cache::SceneCache;
+use crate::scene::sky::Sky;
+use crate::scene::spectrum::{cie_xyz, sample_wavelength, xyz_to_white_balanced_rgb};
 use crate::scene::Scene;
 
+pub mod aov;
+pub mod denoiser;
 mod illumination;
+pub mod mis;
 mod ray_caster;
 
 static NUM_WORKERS: usize = 8;
 static CHUNK_SIZE: usize = 16;
+/// Share of the channel values of a preview image that aren't clipped, under a physical sky.
+/// Other scenes are scaled to their brightest value.
+static SUN_WHITE_POINT_QUANTILE: f64 = 0.999;
 
 struct ImageBuffer {
     width: usize,
     data: Vec<Vector3D>,
 }
 
+impl ImageBuffer {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            data: vec![Vector3D::default(); width * height],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RenderOptions {
+    pub aovs: Vec<Aov>,
+    pub denoise: DenoiseMode,
+    pub mis: MisHeuristic,
+    /// Trace every path at a single wavelength and accumulate CIE XYZ instead of RGB
+    pub spectral: bool,
+    /// Camera rays ignore the backs of all surfaces
+    pub cull_back_faces: bool,
+    /// File to keep the parsed scene geometry in between runs
+    pub scene_cache: Option<PathBuf>,
+    /// Keep the built hierarchy in the scene cache as well
+    pub cache_bvh: bool,
+}
+
+/// How the paths of pixel samples are traced.
+#[derive(Clone, Copy)]
+struct PathSampling {
+    mis: MisHeuristic,
+    spectral: bool,
+    cull_back_faces: bool,
+}
+
 pub struct Raytracer {
     scene: Arc<Scene>,
     ray_caster: Arc<RayCaster>,
     image_buffer: Arc<RwLock<ImageBuffer>>,
+    aov_buffers: Arc<AovBuffers>,
+    options: RenderOptions,
 }
 
 impl Raytracer {
-    pub fn new(scene_path: &Path, ray_caster_path: &Path) -> Self {
+    pub fn new(scene_path: &Path, ray_caster_path: &Path, options: RenderOptions) -> Self {
         let ray_caster = RayCaster::new(ray_caster_path);
-        let scene = Arc::new(Scene::try_read(scene_path).unwrap());
-        let image_buffer = Arc::new(RwLock::new(ImageBuffer {
-            width: ray_caster.width,
-            data: vec![Vector3D::default(); (ray_caster.width * ray_caster.height) as usize],
-        }));
+        let cache = options.scene_cache.as_ref().map(|path| SceneCache {
+            path: path.clone(),
+            include_bvh: options.cache_bvh,
+        });
+        let scene = Arc::new(Scene::try_read(scene_path, cache.as_ref()).unwrap());
+        let image_buffer = Arc::new(RwLock::new(ImageBuffer::new(
+            ray_caster.width,
+            ray_caster.height,
+        )));
+        let mut aovs = options.aovs.clone();
+        if options.denoise.is_enabled() {
+            // Denoiser guides
+            for guide in [Aov::Albedo, Aov::Normal] {
+                if !aovs.contains(&guide) {
+                    aovs.push(guide);
+                }
+            }
+        }
+        let aov_buffers = Arc::new(AovBuffers::new(&aovs, ray_caster.width, ray_caster.height));
         let ray_caster = Arc::new(ray_caster);
         Self {
             scene,
             image_buffer,
+            aov_buffers,
             ray_caster,
+            options,
         }
     }
 
@@ -81,29 +148,102 @@ impl Raytracer {
         lines_done: Arc<AtomicUsize>,
         scene: Arc<Scene>,
         image_buffer: Arc<RwLock<ImageBuffer>>,
+        aov_buffers: Arc<AovBuffers>,
         ray_caster: Arc<RayCaster>,
-        section_number: usize,
-        number_of_sections: usize,
+        (section_number, number_of_sections): (usize, usize),
+        sampling: PathSampling,
     ) {
         let image_width = ray_caster.width;
         let section_width = (image_width - 1) / number_of_sections + 1;
         let start = section_width * section_number;
         let end = min(section_width * (section_number + 1), image_width);
         for x in start..end {
-            for y in 0..ray_caster.height {
-                let illumination =
-                    calculate_illumination(&ray_caster.cast_ray(x, y), scene.borrow(), 10);
-                Self::set_pixel(Arc::clone(&image_buffer), x, y, illumination);
+            // Tiles of pixels down the column, so that packets of camera rays are coherent
+            for tile_start in (0..ray_caster.height).step_by(PACKET_SIZE) {
+                let tile = tile_start..min(tile_start + PACKET_SIZE, ray_caster.height);
+                let pixel_rays: Vec<Vec<Ray>> = tile
+                    .clone()
+                    .map(|y| {
+                        (0..ray_caster.samples)
+                            .map(|sample| match sample {
+                                0 => ray_caster.cast_ray(x, y),
+                                _ => ray_caster.cast_jittered_ray(x, y),
+                            })
+                            .collect()
+                    })
+                    .collect();
+                let pixels = Self::trace_tile(pixel_rays, scene.borrow(), sampling);
+                for (y, (illumination, first_hit)) in tile.zip(pixels) {
+                    let mut illumination = illumination.average(ray_caster.samples);
+                    if sampling.spectral {
+                        illumination = illumination.converted(xyz_to_white_balanced_rgb);
+                    }
+                    Self::set_pixel(Arc::clone(&image_buffer), x, y, illumination.total());
+                    aov_buffers.set_pixel(x, y, &illumination, first_hit.as_ref());
+                }
             }
             lines_done.fetch_add(1, Release);
         }
     }
 
+    /// Summed up light of the camera rays of each pixel of a tile, CIE XYZ in spectral mode,
+    /// and the first hit of the first ray of each pixel. The rays are traced sample by sample,
+    /// so that a packet holds the same sample of neighbouring pixels.
+    fn trace_tile(
+        pixel_rays: Vec<Vec<Ray>>,
+        scene: &Scene,
+        sampling: PathSampling,
+    ) -> Vec<(Illumination, Option<FirstHit>)> {
+        let tile_size = pixel_rays.len();
+        let samples = pixel_rays.first().map_or(0, Vec::len);
+        let mut pixel_rays: Vec<_> = pixel_rays.into_iter().map(Vec::into_iter).collect();
+        let rays: Vec<Ray> = (0..samples * tile_size)
+            .filter_map(|index| pixel_rays[index % tile_size].next())
+            .collect();
+        let wavelengths: Vec<Option<(f64, f64)>> = rays
+            .iter()
+            .map(|_| {
+                sampling
+                    .spectral
+                    .then(|| sample_wavelength(thread_rng().gen()))
+            })
+            .collect();
+        let contexts: Vec<PathContext> = wavelengths
+            .iter()
+            .map(|wavelength| PathContext {
+                mis: sampling.mis,
+                cull_back_faces: sampling.cull_back_faces,
+                wavelength: wavelength.map(|(wavelength, _)| wavelength),
+            })
+            .collect();
+
+        let mut pixels: Vec<(Illumination, Option<FirstHit>)> = (0..tile_size)
+            .map(|_| (Illumination::default(), None))
+            .collect();
+        let traced = trace_primary_rays(&rays, scene, 10, &contexts);
+        for (index, ((sample, sample_hit), wavelength)) in
+            traced.into_iter().zip(wavelengths).enumerate()
+        {
+            let (illumination, first_hit) = &mut pixels[index % tile_size];
+            illumination.accumulate(&match wavelength {
+                None => sample,
+                // Paths carry the same value in all channels then
+                Some((wavelength, pdf)) => sample.weighted(&(cie_xyz(wavelength) / pdf)),
+            });
+            if index < tile_size {
+                *first_hit = sample_hit;
+            }
+        }
+        pixels
+    }
+
     fn start_worker_pool(
         lines_done: Arc<AtomicUsize>,
         scene: Arc<Scene>,
         image_buffer: Arc<RwLock<ImageBuffer>>,
+        aov_buffers: Arc<AovBuffers>,
         ray_caster: Arc<RayCaster>,
+        sampling: PathSampling,
     ) -> ThreadPool {
         let width = ray_caster.width;
         let num_chunks = (width - 1) / CHUNK_SIZE + 1;
@@ -115,15 +255,17 @@ impl Raytracer {
                 let lines_done = Arc::clone(&lines_done);
                 let scene = Arc::clone(&scene);
                 let image_buffer = Arc::clone(&image_buffer);
+                let aov_buffers = Arc::clone(&aov_buffers);
                 let ray_caster = Arc::clone(&ray_caster);
                 move || {
                     Self::trace_section_of_image(
                         lines_done,
                         scene,
                         image_buffer,
+                        aov_buffers,
                         ray_caster,
-                        chunk_idx,
-                        num_chunks,
+                        (chunk_idx, num_chunks),
+                        sampling,
                     );
                 }
             });
@@ -135,26 +277,70 @@ impl Raytracer {
         lines_done: Arc<AtomicUsize>,
         lines_total: usize,
         image_buffer: Arc<RwLock<ImageBuffer>>,
+        aov_buffers: Arc<AovBuffers>,
+        denoise: DenoiseMode,
+        white_quantile: f64,
     ) -> JoinHandle<()> {
         thread::spawn(move || {
             while lines_done.load(Acquire) != lines_total {
                 thread::sleep(Duration::from_secs((10 * lines_total / 250) as u64)); // heuristic
-                info!("Saving image");
-                Self::save_image(Arc::clone(&image_buffer), "intermediate.png");
+                Self::save_dump(
+                    &image_buffer,
+                    &aov_buffers,
+                    denoise.denoise_dumps(),
+                    white_quantile,
+                );
             }
-            info!("Saving image");
-            Self::save_image(image_buffer, "intermediate.png");
+            Self::save_dump(
+                &image_buffer,
+                &aov_buffers,
+                denoise.denoise_final(),
+                white_quantile,
+            );
         })
     }
 
+    fn save_dump(
+        image_buffer: &RwLock<ImageBuffer>,
+        aov_buffers: &AovBuffers,
+        denoise: bool,
+        white_quantile: f64,
+    ) {
+        if denoise {
+            info!("Denoising image");
+            let denoised = denoiser::denoise(
+                &image_buffer.read().unwrap(),
+                &aov_buffers.get(Aov::Albedo).unwrap().read().unwrap(),
+                &aov_buffers.get(Aov::Normal).unwrap().read().unwrap(),
+            );
+            info!("Saving image");
+            Self::save_image(&denoised, "intermediate.png", white_quantile);
+        } else {
+            info!("Saving image");
+            Self::save_image(
+                &image_buffer.read().unwrap(),
+                "intermediate.png",
+                white_quantile,
+            );
+        }
+    }
+
     fn trace_full_image_multiprocess_with_dumps(&mut self) {
+        let white_quantile = match self.scene.sky {
+            Some(Sky::Physical(_)) => SUN_WHITE_POINT_QUANTILE,
+            _ => 1.0,
+        };
         info!("Tracing sky");
         Self::trace_sky(
             Arc::clone(&self.scene),
             Arc::clone(&self.image_buffer),
             Arc::clone(&self.ray_caster),
         );
-        Self::save_image(Arc::clone(&self.image_buffer), "intermediate.png");
+        Self::save_image(
+            &self.image_buffer.read().unwrap(),
+            "intermediate.png",
+            white_quantile,
+        );
 
         let lines_done = Arc::new(AtomicUsize::new(0));
 
@@ -162,12 +348,21 @@ impl Raytracer {
             Arc::clone(&lines_done),
             Arc::clone(&self.scene),
             Arc::clone(&self.image_buffer),
+            Arc::clone(&self.aov_buffers),
             Arc::clone(&self.ray_caster),
+            PathSampling {
+                mis: self.options.mis,
+                spectral: self.options.spectral,
+                cull_back_faces: self.options.cull_back_faces,
+            },
         );
         let dumper_thread = Self::start_dumper_thread(
             Arc::clone(&lines_done),
             self.ray_caster.width,
             Arc::clone(&self.image_buffer),
+            Arc::clone(&self.aov_buffers),
+            self.options.denoise,
+            white_quantile,
         );
 
         info!("Tracing objects");
@@ -183,41 +378,68 @@ impl Raytracer {
 
         workers_pool.join();
         dumper_thread.join().unwrap();
+        self.aov_buffers.save(&self.options.aovs);
         info!("Tracing done");
     }
 
-    fn get_rgb_image(image: Arc<RwLock<ImageBuffer>>) -> RgbImage {
-        let max = image
-            .read()
-            .unwrap()
+    /// Channel value shown as white, the `quantile` of all of them. Below 1 the sun
+    /// or a few fireflies don't make the rest of the image black.
+    fn white_point(image: &ImageBuffer, quantile: f64) -> f64 {
+        let mut values: Vec<f64> = image
             .data
             .iter()
             .flat_map(|vec| [vec.x, vec.y, vec.z])
-            .max_by(|x, y| x.total_cmp(y))
-            .unwrap();
+            .collect();
+        let index = ((values.len() - 1) as f64 * quantile) as usize;
+        let (_, white, brighter) = values.select_nth_unstable_by(index, f64::total_cmp);
+        if *white > 0.0 {
+            *white
+        } else {
+            brighter.iter().copied().fold(0.0, f64::max)
+        }
+    }
+
+    fn get_rgb_image(image: &ImageBuffer, white_quantile: f64) -> RgbImage {
+        let white = Self::white_point(image, white_quantile);
         let bytes: Vec<u8> = image
-            .read()
-            .unwrap()
             .data
-            .clone()
-            .into_iter()
+            .iter()
             .flat_map(|vec| {
                 [
-                    (vec.x / max * 255.) as u8,
-                    (vec.y / max * 255.) as u8,
-                    (vec.z / max * 255.) as u8,
+                    (vec.x / white * 255.) as u8,
+                    (vec.y / white * 255.) as u8,
+                    (vec.z / white * 255.) as u8,
                 ]
             })
             .collect();
 
-        let width = image.read().unwrap().width as u32;
-        let height = image.read().unwrap().data.len() as u32 / width;
+        let width = image.width as u32;
+        let height = image.data.len() as u32 / width;
 
         RgbImage::from_raw(width, height, bytes).unwrap()
     }
 
-    fn save_image(image: Arc<RwLock<ImageBuffer>>, filename: &str) {
-        Self::get_rgb_image(image)
+    fn save_image(image: &ImageBuffer, filename: &str, white_quantile: f64) {
+        Self::get_rgb_image(image, white_quantile)
+            .save(Path::new(filename))
+            .expect("Couldn't save image");
+    }
+
+    fn get_float_image(image: &ImageBuffer) -> Rgb32FImage {
+        let floats: Vec<f32> = image
+            .data
+            .iter()
+            .flat_map(|vec| [vec.x as f32, vec.y as f32, vec.z as f32])
+            .collect();
+
+        let width = image.width as u32;
+        let height = image.data.len() as u32 / width;
+
+        Rgb32FImage::from_raw(width, height, floats).unwrap()
+    }
+
+    fn save_float_image(image: &ImageBuffer, filename: &str) {
+        Self::get_float_image(image)
             .save(Path::new(filename))
             .expect("Couldn't save image");
     }
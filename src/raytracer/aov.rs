@@ -0,0 +1,127 @@
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::geometry::vector::Vector3D;
+use crate::raytracer::illumination::{FirstHit, Illumination};
+use crate::raytracer::{ImageBuffer, Raytracer};
+
+/// Arbitrary output variable: an auxiliary image rendered alongside the beauty pass.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    MaterialId,
+    ObjectId,
+    /// Blue where the camera sees the front of a surface, red where it sees the back
+    Facing,
+    Direct,
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 9] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Facing,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Facing => "facing",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    /// Scalar AOVs are replicated into all three channels.
+    /// Pixels that see the sky get zero geometry and ids.
+    fn value(&self, illumination: &Illumination, first_hit: Option<&FirstHit>) -> Vector3D {
+        let scalar = |value: f64| Vector3D::from([value, value, value]);
+        match (self, first_hit) {
+            (Aov::Direct, _) => illumination.direct.clone(),
+            (Aov::Indirect, _) => illumination.indirect.clone(),
+            (_, None) => Vector3D::default(),
+            (Aov::Depth, Some(hit)) => scalar(hit.depth),
+            (Aov::Normal, Some(hit)) => hit.normal.clone(),
+            (Aov::Albedo, Some(hit)) => hit.albedo.clone(),
+            (Aov::Position, Some(hit)) => hit.position.clone(),
+            (Aov::MaterialId, Some(hit)) => scalar(hit.material_id as f64),
+            (Aov::ObjectId, Some(hit)) => scalar(hit.object_id as f64),
+            (Aov::Facing, Some(hit)) if hit.front_facing => Vector3D::from([0.0, 0.0, 1.0]),
+            (Aov::Facing, Some(_)) => Vector3D::from([1.0, 0.0, 0.0]),
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .into_iter()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| anyhow!("Unknown AOV: {}", s))
+    }
+}
+
+pub struct AovBuffers {
+    buffers: Vec<(Aov, Arc<RwLock<ImageBuffer>>)>,
+}
+
+impl AovBuffers {
+    pub fn new(aovs: &[Aov], width: usize, height: usize) -> Self {
+        Self {
+            buffers: aovs
+                .iter()
+                .map(|aov| (*aov, Arc::new(RwLock::new(ImageBuffer::new(width, height)))))
+                .collect(),
+        }
+    }
+
+    pub fn set_pixel(
+        &self,
+        x: usize,
+        y: usize,
+        illumination: &Illumination,
+        first_hit: Option<&FirstHit>,
+    ) {
+        for (aov, buffer) in self.buffers.iter() {
+            Raytracer::set_pixel(Arc::clone(buffer), x, y, aov.value(illumination, first_hit));
+        }
+    }
+
+    pub(super) fn get(&self, aov: Aov) -> Option<&Arc<RwLock<ImageBuffer>>> {
+        self.buffers
+            .iter()
+            .find(|(buffer_aov, _)| *buffer_aov == aov)
+            .map(|(_, buffer)| buffer)
+    }
+
+    /// Writes each of the requested buffers as a separate float image named `aov_<name>.exr`.
+    pub fn save(&self, aovs: &[Aov]) {
+        for (aov, buffer) in self.buffers.iter().filter(|(aov, _)| aovs.contains(aov)) {
+            info!("Saving {} AOV", aov.name());
+            Raytracer::save_float_image(
+                &buffer.read().unwrap(),
+                &format!("aov_{}.exr", aov.name()),
+            );
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::geometry::vector::Vector3D;
+use crate::raytracer::ImageBuffer;
+
+static ITERATIONS: usize = 5;
+static KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+static COLOR_SIGMA: f64 = 0.5;
+static NORMAL_SIGMA: f64 = 0.1;
+static ALBEDO_SIGMA: f64 = 0.1;
+
+/// Albedo below this is treated as black when demodulating texture from lighting.
+static ALBEDO_EPSILON: f64 = 1e-3;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum DenoiseMode {
+    #[default]
+    None,
+    Final,
+    Dumps,
+    All,
+}
+
+impl DenoiseMode {
+    pub fn is_enabled(&self) -> bool {
+        *self != DenoiseMode::None
+    }
+
+    pub fn denoise_final(&self) -> bool {
+        matches!(self, DenoiseMode::Final | DenoiseMode::All)
+    }
+
+    pub fn denoise_dumps(&self) -> bool {
+        matches!(self, DenoiseMode::Dumps | DenoiseMode::All)
+    }
+}
+
+impl FromStr for DenoiseMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DenoiseMode::None),
+            "final" => Ok(DenoiseMode::Final),
+            "dumps" => Ok(DenoiseMode::Dumps),
+            "all" => Ok(DenoiseMode::All),
+            _ => Err(anyhow!("Unknown denoise mode: {}", s)),
+        }
+    }
+}
+
+fn demodulate(color: &Vector3D, albedo: &Vector3D) -> Vector3D {
+    let divide = |color: f64, albedo: f64| {
+        if albedo > ALBEDO_EPSILON {
+            color / albedo
+        } else {
+            color
+        }
+    };
+    Vector3D {
+        x: divide(color.x, albedo.x),
+        y: divide(color.y, albedo.y),
+        z: divide(color.z, albedo.z),
+    }
+}
+
+fn remodulate(irradiance: &Vector3D, albedo: &Vector3D) -> Vector3D {
+    let multiply = |irradiance: f64, albedo: f64| {
+        if albedo > ALBEDO_EPSILON {
+            irradiance * albedo
+        } else {
+            irradiance
+        }
+    };
+    Vector3D {
+        x: multiply(irradiance.x, albedo.x),
+        y: multiply(irradiance.y, albedo.y),
+        z: multiply(irradiance.z, albedo.z),
+    }
+}
+
+fn edge_stopping_weight(a: &Vector3D, b: &Vector3D, sigma: f64) -> f64 {
+    (-(a - b).f2_norm() / (sigma * sigma)).exp()
+}
+
+fn a_trous_iteration(
+    irradiance: &[Vector3D],
+    albedo: &[Vector3D],
+    normal: &[Vector3D],
+    width: usize,
+    step: usize,
+    color_sigma: f64,
+) -> Vec<Vector3D> {
+    let height = irradiance.len() / width;
+    let mut filtered = Vec::with_capacity(irradiance.len());
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = x + width * y;
+            let mut sum = Vector3D::default();
+            let mut total_weight = 0.;
+
+            for (dy, ky) in KERNEL.iter().enumerate() {
+                for (dx, kx) in KERNEL.iter().enumerate() {
+                    let sample_x = x as isize + (dx as isize - 2) * step as isize;
+                    let sample_y = y as isize + (dy as isize - 2) * step as isize;
+                    if sample_x < 0
+                        || sample_y < 0
+                        || sample_x >= width as isize
+                        || sample_y >= height as isize
+                    {
+                        continue;
+                    }
+                    let sample = sample_x as usize + width * sample_y as usize;
+
+                    let weight = kx
+                        * ky
+                        * edge_stopping_weight(
+                            &irradiance[center],
+                            &irradiance[sample],
+                            color_sigma,
+                        )
+                        * edge_stopping_weight(&normal[center], &normal[sample], NORMAL_SIGMA)
+                        * edge_stopping_weight(&albedo[center], &albedo[sample], ALBEDO_SIGMA);
+                    sum += &irradiance[sample] * weight;
+                    total_weight += weight;
+                }
+            }
+
+            filtered.push(&sum / total_weight);
+        }
+    }
+
+    filtered
+}
+
+/// Edge-avoiding À-Trous wavelet filter (Dammertz et al. 2010).
+/// Lighting is filtered separately from the albedo, so texture detail isn't blurred,
+/// and first hit normals and albedo keep the filter from crossing geometry and material edges.
+pub(super) fn denoise(
+    image: &ImageBuffer,
+    albedo: &ImageBuffer,
+    normal: &ImageBuffer,
+) -> ImageBuffer {
+    let mut irradiance: Vec<Vector3D> = image
+        .data
+        .iter()
+        .zip(albedo.data.iter())
+        .map(|(color, albedo)| demodulate(color, albedo))
+        .collect();
+
+    // Color differences are measured relative to the average brightness,
+    // since the image isn't normalized until it is saved
+    let mean_brightness =
+        irradiance.iter().map(|color| color.len()).sum::<f64>() / irradiance.len() as f64;
+    let mut color_sigma = COLOR_SIGMA * mean_brightness.max(ALBEDO_EPSILON);
+    for iteration in 0..ITERATIONS {
+        irradiance = a_trous_iteration(
+            &irradiance,
+            &albedo.data,
+            &normal.data,
+            image.width,
+            1 << iteration,
+            color_sigma,
+        );
+        color_sigma /= 2.;
+    }
+
+    ImageBuffer {
+        width: image.width,
+        data: irradiance
+            .iter()
+            .zip(albedo.data.iter())
+            .map(|(irradiance, albedo)| remodulate(irradiance, albedo))
+            .collect(),
+    }
+}
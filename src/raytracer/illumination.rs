@@ -1,83 +1,566 @@
-use std::sync::Arc;
+use rand::{thread_rng, Rng};
 
+use crate::geometry::frame::Frame;
 use crate::geometry::intersection::Intersection;
 use crate::geometry::ray::Ray;
 use crate::geometry::vector::Vector3D;
-use crate::geometry::{get_intersection, reflect, EPSILON};
-use crate::scene::material::Material;
+use crate::raytracer::mis::MisHeuristic;
+use crate::scene::bvh::Hit;
+use crate::scene::medium::{Medium, MediumInteraction};
+use crate::scene::object::Object;
+use crate::scene::spectrum::rgb_to_spectrum;
 use crate::scene::Scene;
 
-fn find_all_nontrivial_collisions(ray: &Ray, scene: &Scene) -> Vec<(Intersection, Arc<Material>)> {
-    let mut collisions = vec![];
+enum Collision<'a> {
+    Sky,
+    Polygon(Intersection, Object<'a>),
+}
 
-    for object in scene.objects.iter() {
-        match get_intersection(ray, &object.polygon)
-            .map(|intersection| (intersection, Arc::clone(&object.material)))
-        {
-            None => continue,
-            Some(collision) => collisions.push(collision),
+impl<'a> Collision<'a> {
+    fn new(ray: &Ray, hit: Option<Hit<'a>>) -> Self {
+        match hit {
+            None => Collision::Sky,
+            Some((mut intersection, object)) => {
+                intersection.normal = object.shading_normal(&intersection, ray);
+                Collision::Polygon(intersection, object)
+            }
         }
     }
+}
 
-    collisions
+/// Backs of surfaces a ray passes through.
+#[derive(Clone, Copy)]
+enum BackFaceCulling {
+    /// Rays besides camera rays see both sides of all surfaces, so one-sided surfaces
+    /// still cast shadows when lit from behind
+    None,
+    /// Camera rays pass through the backs of one-sided surfaces
+    OneSided,
+    /// Camera rays with `cull_back_faces` pass through the backs of all surfaces
+    All,
 }
 
-enum Collision {
-    Sky,
-    Polygon(Intersection, Arc<Material>),
+/// Whether the ray stops at `intersection` with `object`. It passes through the backs
+/// of surfaces by `culling`, and through cutout surfaces at random, by their opacity at the hit.
+fn stops_at(
+    ray: &Ray,
+    intersection: &Intersection,
+    object: &Object,
+    culling: BackFaceCulling,
+) -> bool {
+    let culled = match culling {
+        BackFaceCulling::None => false,
+        BackFaceCulling::OneSided => object.material.one_sided,
+        BackFaceCulling::All => true,
+    };
+    if !intersection.front_facing && culled {
+        return false;
+    }
+    !object.material.is_cutout()
+        || thread_rng().gen::<f64>()
+            < object
+                .material
+                .opacity(&object.surface_point(intersection, ray.differential_at(intersection)))
+}
+
+fn get_the_collision<'a>(ray: &Ray, scene: &'a Scene) -> Collision<'a> {
+    let hit = scene
+        .bvh
+        .closest_hit(&scene.mesh, ray, |intersection, object| {
+            stops_at(ray, intersection, object, BackFaceCulling::None)
+        });
+    Collision::new(ray, hit)
+}
+
+/// Light arriving along a ray, split into light that reached the first hit directly
+/// (emission, ambient and scene lights) and light that took at least one more bounce.
+#[derive(Default)]
+pub struct Illumination {
+    pub direct: Vector3D,
+    pub indirect: Vector3D,
+}
+
+impl Illumination {
+    pub fn total(&self) -> Vector3D {
+        &self.direct + &self.indirect
+    }
+
+    pub fn accumulate(&mut self, other: &Illumination) {
+        self.direct += &other.direct;
+        self.indirect += &other.indirect;
+    }
+
+    pub fn weighted(self, weight: &Vector3D) -> Self {
+        Self {
+            direct: self.direct.component_mul(weight),
+            indirect: self.indirect.component_mul(weight),
+        }
+    }
+
+    pub fn converted(self, convert: impl Fn(&Vector3D) -> Vector3D) -> Self {
+        Self {
+            direct: convert(&self.direct),
+            indirect: convert(&self.indirect),
+        }
+    }
+
+    pub fn average(mut self, samples: usize) -> Self {
+        self.direct /= samples as f64;
+        self.indirect /= samples as f64;
+        self
+    }
+}
+
+/// Settings of the path a ray belongs to.
+#[derive(Clone, Copy)]
+pub struct PathContext {
+    pub mis: MisHeuristic,
+    /// Nanometers, when rendering spectrally
+    pub wavelength: Option<f64>,
+    /// Camera rays don't see the backs of surfaces
+    pub cull_back_faces: bool,
+}
+
+impl PathContext {
+    /// Value of `color` along the path, in spectral mode the same in all channels.
+    fn spectrum(&self, color: &Vector3D) -> Vector3D {
+        match self.wavelength {
+            None => color.clone(),
+            Some(wavelength) => {
+                let value = rgb_to_spectrum(color, wavelength);
+                Vector3D::from([value, value, value])
+            }
+        }
+    }
 }
 
-fn get_the_collision(ray: &Ray, scene: &Scene) -> Collision {
-    let nontrivial_collisions = find_all_nontrivial_collisions(ray, scene);
-    if nontrivial_collisions.is_empty() {
-        Collision::Sky
+/// Surface properties at the first intersection of a camera ray.
+pub struct FirstHit {
+    pub depth: f64,
+    pub position: Vector3D,
+    pub normal: Vector3D,
+    pub albedo: Vector3D,
+    pub material_id: usize,
+    pub object_id: usize,
+    pub front_facing: bool,
+}
+
+/// Whether a ray crossing the surface at `intersection` ends up inside the object. Taken from
+/// the side it hits rather than toggled, as rays can enter open meshes through their backs.
+fn inside_after_crossing(intersection: &Intersection) -> bool {
+    intersection.front_facing
+}
+
+/// Medium a ray is in after crossing the surface of `object` to the `inside` side.
+/// Media don't nest, leaving any object leads to the atmosphere.
+fn medium_behind<'a>(object: Object<'a>, inside: bool, scene: &'a Scene) -> Option<&'a Medium> {
+    if inside {
+        object.material.medium.as_ref()
     } else {
-        let (intersection, material) = (*nontrivial_collisions
-            .iter()
-            .min_by(|(a, _), (b, _)| a.distance.total_cmp(&b.distance))
-            .unwrap())
-        .clone();
-        Collision::Polygon(intersection, material)
+        scene.atmosphere.as_ref()
     }
 }
 
-pub fn calculate_illumination(ray: &Ray, scene: &Scene, ttl: usize) -> Vector3D {
-    if ttl == 0 {
+/// Fraction of the light coming from `origin` along `direction` over `distance`, which may be
+/// infinite, through media and their invisible boundaries. Zero if a surface blocks the way.
+/// The surface of the `light` itself, an index in the scene lights, doesn't block it.
+fn transmittance(
+    origin: Vector3D,
+    direction: &Vector3D,
+    distance: f64,
+    light: Option<usize>,
+    inside: bool,
+    medium: Option<&Medium>,
+    scene: &Scene,
+) -> Vector3D {
+    let mut transmittance = Vector3D::from([1.0, 1.0, 1.0]);
+    let mut distance = distance;
+    let mut medium = medium;
+    let mut shadow_ray = Ray {
+        from: origin,
+        direction: direction.clone(),
+        inside,
+        differential: None,
+    };
+
+    loop {
+        match get_the_collision(&shadow_ray, scene) {
+            Collision::Polygon(intersection, object)
+                if intersection.distance < distance
+                    && (light.is_none() || object.light != light) =>
+            {
+                if !object.material.is_interface() {
+                    return Vector3D::default();
+                }
+                if let Some(medium) = medium {
+                    transmittance = transmittance.component_mul(medium.transmittance(
+                        &shadow_ray,
+                        intersection.distance,
+                        &mut thread_rng(),
+                    ));
+                }
+
+                let inside = inside_after_crossing(&intersection);
+                distance -= intersection.distance;
+                medium = medium_behind(object, inside, scene);
+                shadow_ray = Ray {
+                    from: intersection.spawn_origin(direction),
+                    direction: direction.clone(),
+                    inside,
+                    differential: None,
+                };
+            }
+            _ => {
+                return match medium {
+                    None => transmittance,
+                    Some(medium) => transmittance.component_mul(medium.transmittance(
+                        &shadow_ray,
+                        distance,
+                        &mut thread_rng(),
+                    )),
+                };
+            }
+        }
+    }
+}
+
+/// Point that gathers light, on a surface or in a medium.
+enum ShadingPoint<'a> {
+    Surface(&'a Intersection),
+    Medium(&'a Vector3D),
+}
+
+impl ShadingPoint<'_> {
+    fn position(&self) -> &Vector3D {
+        match self {
+            ShadingPoint::Surface(intersection) => &intersection.position,
+            ShadingPoint::Medium(position) => position,
+        }
+    }
+
+    /// Shading normal, zero in media
+    fn normal(&self) -> Vector3D {
+        match self {
+            ShadingPoint::Surface(intersection) => intersection.normal.clone(),
+            ShadingPoint::Medium(_) => Vector3D::default(),
+        }
+    }
+
+    fn spawn_origin(&self, direction: &Vector3D) -> Vector3D {
+        match self {
+            ShadingPoint::Surface(intersection) => intersection.spawn_origin(direction),
+            ShadingPoint::Medium(position) => (*position).clone(),
+        }
+    }
+}
+
+/// One light picked by the light BVH, plus one sample of the sky. `scattering` gives
+/// the BSDF value times the cosine, or the phase function, for a direction towards a light
+/// and the density of sampling that direction with them.
+fn calculate_direct_lighting(
+    point: &ShadingPoint,
+    inside: bool,
+    medium: Option<&Medium>,
+    scattering: &dyn Fn(&Vector3D) -> Option<(Vector3D, f64)>,
+    scene: &Scene,
+    context: PathContext,
+) -> Vector3D {
+    let mut illumination = Vector3D::default();
+    let mut rng = thread_rng();
+    let position = point.position();
+
+    if let Some((light, probability)) = scene.light_bvh.sample(position, &point.normal(), &mut rng)
+    {
+        if let Some(sample) = scene.lights[light].sample(position, &mut rng) {
+            if let Some((value, scattering_pdf)) = scattering(&sample.direction) {
+                let pdf = sample.pdf * probability;
+                let weight = if sample.is_delta {
+                    1.0
+                } else {
+                    context.mis.light_weight(pdf, scattering_pdf)
+                };
+                illumination += (context.spectrum(&value) * (weight / pdf))
+                    .component_mul(context.spectrum(&sample.radiance))
+                    .component_mul(context.spectrum(&transmittance(
+                        point.spawn_origin(&sample.direction),
+                        &sample.direction,
+                        sample.distance,
+                        Some(light),
+                        inside,
+                        medium,
+                        scene,
+                    )));
+            }
+        }
+    }
+
+    if let Some(sample) = scene.sky.as_ref().and_then(|sky| sky.sample(&mut rng)) {
+        if let Some((value, scattering_pdf)) = scattering(&sample.direction) {
+            let weight = context.mis.light_weight(sample.pdf, scattering_pdf);
+            illumination += (context.spectrum(&value) * (weight / sample.pdf))
+                .component_mul(context.spectrum(&sample.radiance))
+                .component_mul(context.spectrum(&transmittance(
+                    point.spawn_origin(&sample.direction),
+                    &sample.direction,
+                    f64::INFINITY,
+                    None,
+                    inside,
+                    medium,
+                    scene,
+                )));
+        }
+    }
+
+    illumination
+}
+
+/// Smooth darkening of light arriving at a grazing angle to a surface whose shading normal
+/// is bent away from its geometry (Chiang et al. 2019), instead of a hard shadow terminator.
+/// Directions below the geometric surface get nothing.
+fn terminator_shadowing(intersection: &Intersection, direction: &Vector3D) -> f64 {
+    let cos_geometric = direction * &intersection.geometric_normal;
+    let cos_shading = direction * &intersection.normal;
+    let cos_normals = &intersection.normal * &intersection.geometric_normal;
+    if cos_geometric <= 0.0 || cos_shading <= 0.0 || cos_normals <= 0.0 {
+        return 0.0;
+    }
+
+    let g = (cos_geometric / (cos_shading * cos_normals)).min(1.0);
+    -g * g * g + g * g + g
+}
+
+/// Vertex a ray was scattered from by a non-delta BSDF sample or in a medium. The vertex has
+/// already gathered the light of sampled light sources, so light the ray hits is weighted against it.
+struct ScatteringVertex {
+    position: Vector3D,
+    /// Zero in media
+    normal: Vector3D,
+    scattering_pdf: f64,
+}
+
+fn shade_sky(
+    ray: &Ray,
+    scene: &Scene,
+    scattered_from: Option<&ScatteringVertex>,
+    context: PathContext,
+) -> Vector3D {
+    match (&scene.sky, scattered_from) {
+        (None, _) => Vector3D::default(),
+        (Some(sky), None) => sky.trace(ray),
+        (Some(sky), Some(vertex)) => {
+            sky.trace_unsampled(ray)
+                + sky.trace_sampled(ray)
+                    * context
+                        .mis
+                        .bsdf_weight(vertex.scattering_pdf, sky.pdf(&ray.direction))
+        }
+    }
+}
+
+fn shade_emission(
+    intersection: &Intersection,
+    object: &Object,
+    scene: &Scene,
+    scattered_from: Option<&ScatteringVertex>,
+    context: PathContext,
+) -> Vector3D {
+    // One-sided emitters only glow to the front
+    if !intersection.front_facing && object.material.one_sided {
         return Vector3D::default();
     }
+    match (object.light, scattered_from) {
+        (Some(light), Some(vertex)) => {
+            let light_pdf = scene.light_bvh.pmf(&vertex.position, &vertex.normal, light)
+                * scene.lights[light].pdf(&vertex.position, &intersection.position);
+            &object.material.intensity * context.mis.bsdf_weight(vertex.scattering_pdf, light_pdf)
+        }
+        _ => object.material.intensity.clone(),
+    }
+}
+
+/// Light scattered back along `ray` at `distance` from its origin inside `medium`.
+fn shade_medium(
+    ray: &Ray,
+    distance: f64,
+    medium: &Medium,
+    scene: &Scene,
+    ttl: usize,
+    context: PathContext,
+) -> Illumination {
+    let position = &ray.from + &ray.direction * distance;
+    let outgoing = -&ray.direction;
+    let phase_scattering = |direction: &Vector3D| {
+        let value = medium.phase().evaluate(&outgoing, direction);
+        Some((Vector3D::from([value, value, value]), value))
+    };
+
+    let mut illumination = Illumination {
+        direct: calculate_direct_lighting(
+            &ShadingPoint::Medium(&position),
+            ray.inside,
+            Some(medium),
+            &phase_scattering,
+            scene,
+            context,
+        ),
+        indirect: Vector3D::default(),
+    };
 
-    let collision = get_the_collision(ray, scene);
+    // The phase function is sampled exactly, so the sample weight is one
+    let incoming = medium.phase().sample(&outgoing, &mut thread_rng());
+    let vertex = ScatteringVertex {
+        position: position.clone(),
+        normal: Vector3D::default(),
+        scattering_pdf: medium.phase().evaluate(&outgoing, &incoming),
+    };
+    let scattered_ray = Ray {
+        from: position,
+        direction: incoming,
+        inside: ray.inside,
+        differential: None,
+    };
+    illumination.indirect += calculate_illumination(
+        &scattered_ray,
+        scene,
+        ttl - 1,
+        Some(medium),
+        Some(&vertex),
+        context,
+    );
 
+    illumination
+}
+
+/// Light arriving along `ray` from its `collision`, `medium` is the medium the ray ends in.
+fn shade<'a>(
+    ray: &Ray,
+    collision: Collision<'a>,
+    scene: &'a Scene,
+    ttl: usize,
+    medium: Option<&'a Medium>,
+    scattered_from: Option<&ScatteringVertex>,
+    context: PathContext,
+) -> Illumination {
     match collision {
-        Collision::Sky => match scene.cube_map {
-            None => Vector3D::default(),
-            Some(ref cube_map) => cube_map.trace(ray),
+        Collision::Sky => Illumination {
+            direct: context.spectrum(&shade_sky(ray, scene, scattered_from, context)),
+            indirect: Vector3D::default(),
         },
 
-        Collision::Polygon(intersection, _) if ray.inside => {
-            let phased_ray = Ray {
-                from: intersection.position,
+        Collision::Polygon(intersection, object) if object.material.is_interface() => {
+            // The ray goes on unchanged, only into another medium
+            let inside = inside_after_crossing(&intersection);
+            let crossed_ray = Ray {
+                differential: ray.differential_at(&intersection),
+                from: intersection.spawn_origin(&ray.direction),
                 direction: ray.direction.clone(),
-                inside: !ray.inside,
-            }
-            .propagate(EPSILON);
-            calculate_illumination(&phased_ray, scene, ttl - 1)
+                inside,
+            };
+            shade_through_medium(
+                &crossed_ray,
+                get_the_collision(&crossed_ray, scene),
+                scene,
+                ttl,
+                medium_behind(object, inside, scene),
+                scattered_from,
+                context,
+            )
         }
 
-        Collision::Polygon(intersection, material) => {
-            let mut illumination = material.intensity.clone() + material.ambient_color.clone();
-
-            if material.albedo.y != 0.0 {
-                // reflected
-                let reflected_direction = reflect(&ray.direction, &intersection.normal);
-                let reflected_ray = Ray {
-                    from: intersection.position,
-                    direction: reflected_direction,
-                    inside: !ray.inside,
+        Collision::Polygon(intersection, object) => {
+            let differential = ray.differential_at(&intersection);
+            let material = object
+                .material
+                .at_surface(&object.surface_point(&intersection, differential.clone()));
+            let bsdf = material.bsdf();
+            let frame = Frame::from_normal(&intersection.normal);
+            let outgoing = frame.to_local(&-&ray.direction);
+            let surface_scattering = |direction: &Vector3D| {
+                let incoming = frame.to_local(direction);
+                let shadowing = terminator_shadowing(&intersection, direction);
+                if incoming.z <= 0.0 || shadowing == 0.0 {
+                    return None;
                 }
-                .propagate(EPSILON);
-                illumination +=
-                    calculate_illumination(&reflected_ray, scene, ttl - 1) * material.albedo.y;
+                Some((
+                    bsdf.evaluate(&outgoing, &incoming) * (incoming.z * shadowing),
+                    bsdf.pdf(&outgoing, &incoming),
+                ))
+            };
+
+            let mut illumination = Illumination {
+                direct: context.spectrum(
+                    &(shade_emission(&intersection, &object, scene, scattered_from, context)
+                        + material.ambient_color()),
+                ) + calculate_direct_lighting(
+                    &ShadingPoint::Surface(&intersection),
+                    ray.inside,
+                    medium,
+                    &surface_scattering,
+                    scene,
+                    context,
+                ),
+                indirect: Vector3D::default(),
+            };
+
+            let scattered = bsdf
+                .sample(
+                    &outgoing,
+                    !intersection.front_facing,
+                    context.wavelength,
+                    &mut thread_rng(),
+                )
+                .map(|sample| {
+                    let direction = frame.to_world(&sample.incoming);
+                    let shadowing = if sample.incoming.z >= 0.0 {
+                        terminator_shadowing(&intersection, &direction)
+                    } else if &direction * &intersection.geometric_normal < 0.0 {
+                        1.0
+                    } else {
+                        // Refracted by the shading normal, but not through the geometry
+                        0.0
+                    };
+                    (sample, direction, shadowing)
+                })
+                .filter(|(_, _, shadowing)| *shadowing > 0.0);
+            if let Some((sample, direction, shadowing)) = scattered {
+                let transmitted = sample.incoming.z < 0.0;
+                let inside = if transmitted {
+                    inside_after_crossing(&intersection)
+                } else {
+                    ray.inside
+                };
+                // Footprints are only followed through specular bounces
+                let differential = differential
+                    .filter(|_| sample.is_delta)
+                    .map(|differential| {
+                        differential.specular(&-&ray.direction, &direction, &intersection.normal)
+                    });
+                let scattered_ray = Ray {
+                    from: intersection.spawn_origin(&direction),
+                    direction,
+                    inside,
+                    differential,
+                };
+                let scattered_medium = if transmitted {
+                    medium_behind(object, inside, scene)
+                } else {
+                    medium
+                };
+                let vertex = ScatteringVertex {
+                    position: intersection.position,
+                    normal: intersection.normal,
+                    scattering_pdf: sample.pdf,
+                };
+                let scattered_from = if sample.is_delta { None } else { Some(&vertex) };
+                illumination.indirect += calculate_illumination(
+                    &scattered_ray,
+                    scene,
+                    ttl - 1,
+                    scattered_medium,
+                    scattered_from,
+                    context,
+                )
+                .component_mul(context.spectrum(&(sample.weight * shadowing)));
             }
 
             illumination
@@ -85,9 +568,210 @@ pub fn calculate_illumination(ray: &Ray, scene: &Scene, ttl: usize) -> Vector3D
     }
 }
 
+/// Light arriving along `ray`, which travels through `medium` until its `collision`
+/// unless it scatters in the medium before.
+fn shade_through_medium<'a>(
+    ray: &Ray,
+    collision: Collision<'a>,
+    scene: &'a Scene,
+    ttl: usize,
+    medium: Option<&'a Medium>,
+    scattered_from: Option<&ScatteringVertex>,
+    context: PathContext,
+) -> Illumination {
+    let Some(medium) = medium else {
+        return shade(ray, collision, scene, ttl, None, scattered_from, context);
+    };
+
+    let max_distance = match collision {
+        Collision::Sky => f64::INFINITY,
+        Collision::Polygon(ref intersection, _) => intersection.distance,
+    };
+    match medium.sample_interaction(ray, max_distance, &mut thread_rng()) {
+        MediumInteraction::Scatter { distance, weight } => {
+            shade_medium(ray, distance, medium, scene, ttl, context)
+                .weighted(&context.spectrum(&weight))
+        }
+        MediumInteraction::Absorb { emission } => Illumination {
+            direct: context.spectrum(&emission),
+            indirect: Vector3D::default(),
+        },
+        MediumInteraction::Pass { weight } => shade(
+            ray,
+            collision,
+            scene,
+            ttl,
+            Some(medium),
+            scattered_from,
+            context,
+        )
+        .weighted(&context.spectrum(&weight)),
+    }
+}
+
+fn calculate_illumination(
+    ray: &Ray,
+    scene: &Scene,
+    ttl: usize,
+    medium: Option<&Medium>,
+    scattered_from: Option<&ScatteringVertex>,
+    context: PathContext,
+) -> Vector3D {
+    if ttl == 0 {
+        return Vector3D::default();
+    }
+
+    shade_through_medium(
+        ray,
+        get_the_collision(ray, scene),
+        scene,
+        ttl,
+        medium,
+        scattered_from,
+        context,
+    )
+    .total()
+}
+
+/// Same as `calculate_illumination`, but keeps the direct/indirect split and reports
+/// what the ray hit first. Used for camera rays, which start in the atmosphere, and are
+/// traced to their first hits together, as they are coherent. Each ray has its own `contexts` entry.
+pub fn trace_primary_rays(
+    rays: &[Ray],
+    scene: &Scene,
+    ttl: usize,
+    contexts: &[PathContext],
+) -> Vec<(Illumination, Option<FirstHit>)> {
+    if ttl == 0 {
+        return rays
+            .iter()
+            .map(|_| (Illumination::default(), None))
+            .collect();
+    }
+
+    let hits = scene
+        .bvh
+        .closest_hits(&scene.mesh, rays, |index, intersection, object| {
+            let culling = if contexts[index].cull_back_faces {
+                BackFaceCulling::All
+            } else {
+                BackFaceCulling::OneSided
+            };
+            stops_at(&rays[index], intersection, object, culling)
+        });
+    rays.iter()
+        .zip(hits)
+        .zip(contexts)
+        .map(|((ray, hit), &context)| {
+            let collision = Collision::new(ray, hit);
+            let first_hit = match collision {
+                Collision::Sky => None,
+                Collision::Polygon(ref intersection, object) => Some(FirstHit {
+                    depth: intersection.distance,
+                    position: intersection.position.clone(),
+                    normal: intersection.normal.clone(),
+                    albedo: object
+                        .material
+                        .at_surface(
+                            &object.surface_point(intersection, ray.differential_at(intersection)),
+                        )
+                        .bsdf()
+                        .albedo(),
+                    material_id: object.material.id,
+                    object_id: object.object_id,
+                    front_facing: intersection.front_facing,
+                }),
+            };
+
+            (
+                shade_through_medium(
+                    ray,
+                    collision,
+                    scene,
+                    ttl,
+                    scene.atmosphere.as_ref(),
+                    None,
+                    context,
+                ),
+                first_hit,
+            )
+        })
+        .collect()
+}
+
 pub fn get_sky(ray: &Ray, scene: &Scene) -> Vector3D {
-    match scene.cube_map {
+    match scene.sky {
         None => Vector3D::default(),
-        Some(ref cube_map) => cube_map.trace(ray),
+        Some(ref sky) => sky.trace(ray),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Quad in the z = 0 plane facing +z with a material that only bounds a fog.
+    fn fog_quad(sides: usize) -> Scene {
+        Scene::from_text(
+            "mtllib materials.mtl\n\
+             v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\n\
+             usemtl fog\nf 1 2 3 4\n",
+            &format!("newmtl fog\nd 0\nMa 1 1 1\nsides {}\n", sides),
+        )
+    }
+
+    fn transmittance_along_z(scene: &Scene, from_z: f64) -> f64 {
+        let direction = Vector3D::from([0.0, 0.0, -from_z.signum()]);
+        transmittance(
+            Vector3D::from([0.0, 0.0, from_z]),
+            &direction,
+            2.0 * from_z.abs(),
+            None,
+            false,
+            None,
+            scene,
+        )
+        .x
+    }
+
+    #[test]
+    fn rays_through_the_back_of_an_open_quad_stay_outside() {
+        for sides in [1, 2] {
+            let scene = fog_quad(sides);
+            assert_eq!(transmittance_along_z(&scene, -1.0), 1.0);
+            let through_front = transmittance_along_z(&scene, 1.0);
+            assert!((through_front - (-1.0f64).exp()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn one_sided_surfaces_only_let_camera_rays_through_their_backs() {
+        let scene = Scene::from_text(
+            "mtllib materials.mtl\n\
+             v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\n\
+             usemtl wall\nf 1 2 3 4\n",
+            "newmtl wall\nKd 1 1 1\nsides 1\n",
+        );
+        // Shadow rays are blocked from either side
+        assert_eq!(transmittance_along_z(&scene, -1.0), 0.0);
+        assert_eq!(transmittance_along_z(&scene, 1.0), 0.0);
+
+        let hits = |from_z: f64, culling| {
+            let ray = Ray {
+                from: Vector3D::from([0.0, 0.0, from_z]),
+                direction: Vector3D::from([0.0, 0.0, -from_z.signum()]),
+                inside: false,
+                differential: None,
+            };
+            scene
+                .bvh
+                .closest_hit(&scene.mesh, &ray, |intersection, object| {
+                    stops_at(&ray, intersection, object, culling)
+                })
+                .is_some()
+        };
+        assert!(hits(1.0, BackFaceCulling::OneSided));
+        assert!(!hits(-1.0, BackFaceCulling::OneSided));
+        assert!(hits(-1.0, BackFaceCulling::None));
     }
 }
@@ -0,0 +1,58 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// How light sampling and BSDF sampling are combined with multiple importance sampling (Veach 1997).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum MisHeuristic {
+    /// Only light sampling gathers light of the lights it can sample
+    None,
+    Balance,
+    #[default]
+    Power,
+}
+
+impl MisHeuristic {
+    fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (pdf, other_pdf) = match self {
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+            _ => (pdf, other_pdf),
+        };
+        if pdf + other_pdf == 0.0 {
+            0.0
+        } else {
+            pdf / (pdf + other_pdf)
+        }
+    }
+
+    /// Weight of light reaching a point by sampling a light, whose direction
+    /// the BSDF would sample with `bsdf_pdf`.
+    pub fn light_weight(&self, light_pdf: f64, bsdf_pdf: f64) -> f64 {
+        match self {
+            MisHeuristic::None => 1.0,
+            _ => self.weight(light_pdf, bsdf_pdf),
+        }
+    }
+
+    /// Weight of light found by following a BSDF sample, which light sampling
+    /// would pick with `light_pdf`.
+    pub fn bsdf_weight(&self, bsdf_pdf: f64, light_pdf: f64) -> f64 {
+        match self {
+            MisHeuristic::None => 0.0,
+            _ => self.weight(bsdf_pdf, light_pdf),
+        }
+    }
+}
+
+impl FromStr for MisHeuristic {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(MisHeuristic::None),
+            "balance" => Ok(MisHeuristic::Balance),
+            "power" => Ok(MisHeuristic::Power),
+            _ => Err(anyhow!("Unknown MIS heuristic: {}", s)),
+        }
+    }
+}
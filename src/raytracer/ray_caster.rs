@@ -1,9 +1,10 @@
 use std::fs::File;
 use std::path::Path;
 
+use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
-use crate::geometry::ray::Ray;
+use crate::geometry::ray::{Ray, RayDifferential};
 use crate::geometry::vector::Vector3D;
 use crate::geometry::EPSILON;
 
@@ -14,11 +15,18 @@ pub struct RayCasterConfig {
     pub fov: f64,
     pub look_from: Vector3D,
     pub look_to: Vector3D,
+    #[serde(default = "default_samples")]
+    pub samples: usize,
+}
+
+fn default_samples() -> usize {
+    1
 }
 
 pub struct RayCaster {
     pub height: usize,
     pub width: usize,
+    pub samples: usize,
 
     origin: Vector3D,
     forward: Vector3D,
@@ -36,7 +44,7 @@ impl RayCaster {
 
         let forward = (&config.look_to - &config.look_from).normalize();
 
-        let mut pixel_right = forward.cross(&Vector3D::from([0., 1., 0.]));
+        let mut pixel_right = forward.cross(Vector3D::from([0., 1., 0.]));
         if pixel_right.len() < EPSILON {
             pixel_right = Vector3D::from([0., 0., 1.]);
         } else {
@@ -51,6 +59,7 @@ impl RayCaster {
         RayCaster {
             height: config.height,
             width: config.width,
+            samples: config.samples,
             origin: config.look_from,
             forward,
             pixel_right,
@@ -59,13 +68,40 @@ impl RayCaster {
     }
 
     pub fn cast_ray(&self, x: usize, y: usize) -> Ray {
-        let direction = (&self.forward + &self.pixel_right * (x as f64 - self.width as f64 / 2.0)
-            - &self.pixel_up * (y as f64 - self.height as f64 / 2.0))
-            .normalize();
+        self.cast_subpixel_ray(x as f64, y as f64)
+    }
+
+    /// Ray through a uniformly random point of the pixel, for antialiasing.
+    pub fn cast_jittered_ray(&self, x: usize, y: usize) -> Ray {
+        let mut rng = thread_rng();
+        self.cast_subpixel_ray(
+            x as f64 + rng.gen_range(-0.5..0.5),
+            y as f64 + rng.gen_range(-0.5..0.5),
+        )
+    }
+
+    fn cast_subpixel_ray(&self, x: f64, y: f64) -> Ray {
+        let direction = &self.forward + &self.pixel_right * (x - self.width as f64 / 2.0)
+            - &self.pixel_up * (y - self.height as f64 / 2.0);
+        let length = direction.len();
+        let direction = direction / length;
+
+        // Samples of a pixel share its footprint, so each one covers less of it
+        let spacing = (1.0 / (self.samples as f64).sqrt()).max(0.125);
+        let derivative =
+            |step: Vector3D| (&step - &direction * (&direction * &step)) * (spacing / length);
+        let differential = RayDifferential {
+            origin: [Vector3D::default(), Vector3D::default()],
+            direction: [
+                derivative(self.pixel_right.clone()),
+                derivative(-&self.pixel_up),
+            ],
+        };
         Ray {
             from: self.origin.clone(),
             direction,
             inside: false,
+            differential: Some(differential),
         }
     }
 }
@@ -1,26 +1,69 @@
 use std::path::Path;
 
-use cube_map::CubeMap;
+use bvh::Bvh;
+use cache::SceneCache;
+use light::bvh::LightBvh;
 use light::Light;
-use object::Object;
+use medium::Medium;
+use mesh::Mesh;
+use sky::Sky;
 
+pub mod bsdf;
+pub mod bvh;
+pub mod cache;
 pub mod cube_map;
+pub mod distribution;
+pub mod environment_map;
 pub mod light;
 pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod microfacet;
 pub mod object;
+pub mod physical_sky;
 mod reader;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
 
 pub struct Scene {
-    pub objects: Vec<Object>,
-    pub lights: Vec<Light>,
-    pub cube_map: Option<CubeMap>,
+    pub mesh: Mesh,
+    pub bvh: Bvh,
+    /// Scene lights followed by area lights of the emissive triangles
+    pub lights: Vec<Box<dyn Light>>,
+    pub light_bvh: LightBvh,
+    pub sky: Option<Sky>,
+    /// Medium filling the space outside of objects
+    pub atmosphere: Option<Medium>,
 }
 
 impl Scene {
-    pub fn try_read(file_path: &Path) -> anyhow::Result<Self> {
-        match reader::read_scene(file_path) {
+    pub fn try_read(file_path: &Path, cache: Option<&SceneCache>) -> anyhow::Result<Self> {
+        match reader::read_scene(file_path, cache) {
             Ok(scene) => Ok(scene),
             Err(err) => Err(err.context(format!(", reading scene from {}", file_path.display()))),
         }
     }
 }
+
+#[cfg(test)]
+impl Scene {
+    /// Scene of an .obj and the `materials.mtl` it may refer to, given as text.
+    pub fn from_text(obj: &str, materials: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+        let directory = std::env::temp_dir().join(format!(
+            "raytracer22-test-{}-{}",
+            std::process::id(),
+            NEXT_DIRECTORY.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("materials.mtl"), materials).unwrap();
+        let path = directory.join("scene.obj");
+        std::fs::write(&path, obj).unwrap();
+        let scene = Scene::try_read(&path, None).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        scene
+    }
+}
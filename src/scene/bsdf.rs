@@ -0,0 +1,59 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::vector::Vector3D;
+
+pub mod dielectric;
+pub mod emissive;
+pub mod glossy;
+pub mod lambertian;
+pub mod mirror;
+pub mod mixture;
+
+pub struct BsdfSample {
+    /// Sampled direction towards the light, in the local shading frame.
+    /// Points below the surface (`z < 0`) when the path was transmitted.
+    pub incoming: Vector3D,
+    /// `f * |cos| / pdf` of the sample.
+    pub weight: Vector3D,
+    pub pdf: f64,
+    /// Sampled from a delta distribution, so `evaluate` and `pdf` can't reproduce it.
+    pub is_delta: bool,
+}
+
+/// Scattering model of a surface. Directions are in the local shading frame,
+/// where the normal is +z and faces the outgoing (towards the viewer) direction.
+pub trait Bsdf: Send + Sync {
+    fn evaluate(&self, outgoing: &Vector3D, incoming: &Vector3D) -> Vector3D;
+
+    /// `inside` tells whether `outgoing` lies inside the object,
+    /// `wavelength` is given in nanometers when rendering spectrally.
+    fn sample(
+        &self,
+        outgoing: &Vector3D,
+        inside: bool,
+        wavelength: Option<f64>,
+        rng: &mut ThreadRng,
+    ) -> Option<BsdfSample>;
+
+    fn pdf(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64;
+
+    /// Average reflected color, used as a guide for AOVs and the denoiser.
+    fn albedo(&self) -> Vector3D;
+}
+
+pub fn sample_cosine_hemisphere(rng: &mut ThreadRng) -> Vector3D {
+    let radius = rng.gen::<f64>().sqrt();
+    let phi = 2.0 * PI * rng.gen::<f64>();
+    Vector3D {
+        x: radius * phi.cos(),
+        y: radius * phi.sin(),
+        z: (1.0 - radius * radius).max(0.0).sqrt(),
+    }
+}
+
+pub fn luminance(color: &Vector3D) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
@@ -0,0 +1,107 @@
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::{Bsdf, BsdfSample};
+
+/// Index of refraction changing with wavelength, which splits white light into colors.
+/// Coefficients are for wavelengths in micrometers, like in glass catalogs.
+#[derive(Clone)]
+pub enum Dispersion {
+    /// n = A + B/λ² + C/λ⁴
+    Cauchy { a: f64, b: f64, c: f64 },
+    /// n² = 1 + Σ Bᵢλ²/(λ² - Cᵢ)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Index of refraction at a wavelength in nanometers.
+    pub fn refraction_index(&self, wavelength: f64) -> f64 {
+        let wavelength2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b, c } => a + b / wavelength2 + c / (wavelength2 * wavelength2),
+            Dispersion::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c)
+                    .map(|(b, c)| b * wavelength2 / (wavelength2 - c))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+}
+
+/// Smooth glass-like boundary that either reflects or refracts, `illum 4`, `6`, `7` and `9`.
+pub struct Dielectric {
+    pub refraction_index: f64,
+    /// Used instead of `refraction_index` when rendering spectrally
+    pub dispersion: Option<Dispersion>,
+}
+
+/// Fraction of light reflected at a dielectric boundary with relative index of refraction `eta`.
+pub fn fresnel_dielectric(cos_incident: f64, eta: f64) -> f64 {
+    let sin2_transmitted = (1.0 - cos_incident * cos_incident) / (eta * eta);
+    if sin2_transmitted >= 1.0 {
+        // total internal reflection
+        return 1.0;
+    }
+    let cos_transmitted = (1.0 - sin2_transmitted).sqrt();
+
+    let parallel = (eta * cos_incident - cos_transmitted) / (eta * cos_incident + cos_transmitted);
+    let perpendicular =
+        (cos_incident - eta * cos_transmitted) / (cos_incident + eta * cos_transmitted);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+impl Bsdf for Dielectric {
+    fn evaluate(&self, _outgoing: &Vector3D, _incoming: &Vector3D) -> Vector3D {
+        Vector3D::default()
+    }
+
+    fn sample(
+        &self,
+        outgoing: &Vector3D,
+        inside: bool,
+        wavelength: Option<f64>,
+        rng: &mut ThreadRng,
+    ) -> Option<BsdfSample> {
+        let refraction_index = match (&self.dispersion, wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.refraction_index(wavelength),
+            _ => self.refraction_index,
+        };
+        let eta = if inside {
+            1.0 / refraction_index
+        } else {
+            refraction_index
+        };
+        let reflectance = fresnel_dielectric(outgoing.z, eta);
+
+        if rng.gen::<f64>() < reflectance {
+            return Some(BsdfSample {
+                incoming: Vector3D::from([-outgoing.x, -outgoing.y, outgoing.z]),
+                weight: Vector3D::from([1.0, 1.0, 1.0]),
+                pdf: reflectance,
+                is_delta: true,
+            });
+        }
+
+        let sin2_transmitted = (1.0 - outgoing.z * outgoing.z) / (eta * eta);
+        Some(BsdfSample {
+            incoming: Vector3D {
+                x: -outgoing.x / eta,
+                y: -outgoing.y / eta,
+                z: -(1.0 - sin2_transmitted).sqrt(),
+            },
+            weight: Vector3D::from([1.0, 1.0, 1.0]),
+            pdf: 1.0 - reflectance,
+            is_delta: true,
+        })
+    }
+
+    fn pdf(&self, _outgoing: &Vector3D, _incoming: &Vector3D) -> f64 {
+        0.0
+    }
+
+    fn albedo(&self) -> Vector3D {
+        Vector3D::from([1.0, 1.0, 1.0])
+    }
+}
@@ -0,0 +1,32 @@
+use rand::rngs::ThreadRng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::{Bsdf, BsdfSample};
+
+/// Surface that doesn't scatter any light and only shows its own `Ke` emission,
+/// `illum 0`. Meant for lamps.
+pub struct Emissive;
+
+impl Bsdf for Emissive {
+    fn evaluate(&self, _outgoing: &Vector3D, _incoming: &Vector3D) -> Vector3D {
+        Vector3D::default()
+    }
+
+    fn sample(
+        &self,
+        _outgoing: &Vector3D,
+        _inside: bool,
+        _wavelength: Option<f64>,
+        _rng: &mut ThreadRng,
+    ) -> Option<BsdfSample> {
+        None
+    }
+
+    fn pdf(&self, _outgoing: &Vector3D, _incoming: &Vector3D) -> f64 {
+        0.0
+    }
+
+    fn albedo(&self) -> Vector3D {
+        Vector3D::default()
+    }
+}
@@ -0,0 +1,116 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::reflect;
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::{luminance, sample_cosine_hemisphere, Bsdf, BsdfSample};
+use crate::scene::microfacet::{fresnel_schlick, Ggx};
+
+/// Lambertian diffuse base under a GGX specular layer, `illum 2`.
+/// The base only gets the light the layer doesn't reflect, so the two never add up to more than came in.
+pub struct Glossy {
+    pub diffuse: Vector3D,
+    pub f0: Vector3D,
+    pub microfacets: Ggx,
+}
+
+impl Glossy {
+    /// Diffuse color seen from `outgoing`, less what the specular layer reflects.
+    fn diffuse(&self, outgoing: &Vector3D) -> Vector3D {
+        self.diffuse
+            .component_mul(Vector3D::from([1.0; 3]) - fresnel_schlick(&self.f0, outgoing.z))
+    }
+
+    fn specular_probability(&self, outgoing: &Vector3D) -> f64 {
+        let diffuse = luminance(&self.diffuse(outgoing)).clamp(0.0, 1.0);
+        if diffuse <= 0.0 {
+            return 1.0;
+        }
+        let specular = luminance(&fresnel_schlick(&self.f0, outgoing.z)).clamp(0.0, 1.0);
+        (specular / (specular + diffuse)).clamp(0.1, 0.9)
+    }
+}
+
+impl Bsdf for Glossy {
+    fn evaluate(&self, outgoing: &Vector3D, incoming: &Vector3D) -> Vector3D {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return Vector3D::default();
+        }
+        self.diffuse(outgoing) / PI + self.microfacets.evaluate(outgoing, incoming, &self.f0)
+    }
+
+    fn sample(
+        &self,
+        outgoing: &Vector3D,
+        _inside: bool,
+        _wavelength: Option<f64>,
+        rng: &mut ThreadRng,
+    ) -> Option<BsdfSample> {
+        let incoming = if rng.gen::<f64>() < self.specular_probability(outgoing) {
+            let half = self
+                .microfacets
+                .sample_visible_normal(outgoing, rng.gen(), rng.gen());
+            reflect(&-outgoing, &half)
+        } else {
+            sample_cosine_hemisphere(rng)
+        };
+
+        let pdf = self.pdf(outgoing, &incoming);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: self.evaluate(outgoing, &incoming) * (incoming.z / pdf),
+            incoming,
+            pdf,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64 {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return 0.0;
+        }
+        let specular_probability = self.specular_probability(outgoing);
+        specular_probability * self.microfacets.pdf(outgoing, incoming)
+            + (1.0 - specular_probability) * incoming.z / PI
+    }
+
+    fn albedo(&self) -> Vector3D {
+        let albedo = self
+            .diffuse
+            .component_mul(Vector3D::from([1.0; 3]) - &self.f0)
+            + &self.f0;
+        Vector3D::from([0, 1, 2].map(|channel| albedo[channel].clamp(0.0, 1.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::thread_rng;
+
+    use super::*;
+
+    #[test]
+    fn reflects_no_more_light_than_comes_in() {
+        let glossy = Glossy {
+            diffuse: Vector3D::from([1.0; 3]),
+            f0: Vector3D::from([0.9; 3]),
+            microfacets: Ggx::new(0.5),
+        };
+        let mut rng = thread_rng();
+        for cos in [1.0, 0.5, 0.1] {
+            let outgoing = Vector3D::from([(1.0f64 - cos * cos).sqrt(), 0.0, cos]);
+            let samples = 100_000;
+            let reflected = (0..samples)
+                .filter_map(|_| glossy.sample(&outgoing, false, None, &mut rng))
+                .map(|sample| sample.weight.x)
+                .sum::<f64>()
+                / samples as f64;
+            assert!(reflected <= 1.01, "{} reflected at cos {}", reflected, cos);
+        }
+        assert!(glossy.albedo().x <= 1.0);
+    }
+}
@@ -0,0 +1,47 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::{sample_cosine_hemisphere, Bsdf, BsdfSample};
+
+/// Ideal diffuse reflection, `illum 1`.
+pub struct Lambertian {
+    pub color: Vector3D,
+}
+
+impl Bsdf for Lambertian {
+    fn evaluate(&self, outgoing: &Vector3D, incoming: &Vector3D) -> Vector3D {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return Vector3D::default();
+        }
+        &self.color / PI
+    }
+
+    fn sample(
+        &self,
+        _outgoing: &Vector3D,
+        _inside: bool,
+        _wavelength: Option<f64>,
+        rng: &mut ThreadRng,
+    ) -> Option<BsdfSample> {
+        let incoming = sample_cosine_hemisphere(rng);
+        Some(BsdfSample {
+            pdf: incoming.z / PI,
+            incoming,
+            weight: self.color.clone(),
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64 {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return 0.0;
+        }
+        incoming.z / PI
+    }
+
+    fn albedo(&self) -> Vector3D {
+        self.color.clone()
+    }
+}
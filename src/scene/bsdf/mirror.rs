@@ -0,0 +1,40 @@
+use rand::rngs::ThreadRng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::{Bsdf, BsdfSample};
+use crate::scene::microfacet::fresnel_schlick;
+
+/// Perfect specular reflection, `illum 3`, `5` and `8`, getting brighter at grazing angles.
+pub struct Mirror {
+    /// Reflectance at normal incidence
+    pub f0: Vector3D,
+}
+
+impl Bsdf for Mirror {
+    fn evaluate(&self, _outgoing: &Vector3D, _incoming: &Vector3D) -> Vector3D {
+        Vector3D::default()
+    }
+
+    fn sample(
+        &self,
+        outgoing: &Vector3D,
+        _inside: bool,
+        _wavelength: Option<f64>,
+        _rng: &mut ThreadRng,
+    ) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            incoming: Vector3D::from([-outgoing.x, -outgoing.y, outgoing.z]),
+            weight: fresnel_schlick(&self.f0, outgoing.z),
+            pdf: 1.0,
+            is_delta: true,
+        })
+    }
+
+    fn pdf(&self, _outgoing: &Vector3D, _incoming: &Vector3D) -> f64 {
+        0.0
+    }
+
+    fn albedo(&self) -> Vector3D {
+        self.f0.clone()
+    }
+}
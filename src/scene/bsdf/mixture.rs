@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::{Bsdf, BsdfSample};
+
+/// Weighted sum of other BSDFs, used for materials described by the `al` key
+/// instead of an `illum` model. Weights don't have to sum up to one.
+pub struct Mixture {
+    pub components: Vec<(f64, Arc<dyn Bsdf>)>,
+}
+
+impl Mixture {
+    fn total_weight(&self) -> f64 {
+        self.components.iter().map(|(weight, _)| weight).sum()
+    }
+}
+
+impl Bsdf for Mixture {
+    fn evaluate(&self, outgoing: &Vector3D, incoming: &Vector3D) -> Vector3D {
+        self.components
+            .iter()
+            .fold(Vector3D::default(), |sum, (weight, bsdf)| {
+                sum + bsdf.evaluate(outgoing, incoming) * *weight
+            })
+    }
+
+    fn sample(
+        &self,
+        outgoing: &Vector3D,
+        inside: bool,
+        wavelength: Option<f64>,
+        rng: &mut ThreadRng,
+    ) -> Option<BsdfSample> {
+        let total_weight = self.total_weight();
+        let mut choice = rng.gen::<f64>() * total_weight;
+        let (weight, bsdf) = self
+            .components
+            .iter()
+            .find(|(weight, _)| {
+                choice -= weight;
+                choice < 0.0
+            })
+            .or(self.components.last())?;
+
+        let sample = bsdf.sample(outgoing, inside, wavelength, rng)?;
+        if sample.is_delta {
+            let probability = weight / total_weight;
+            return Some(BsdfSample {
+                weight: &sample.weight * (weight / probability),
+                pdf: sample.pdf * probability,
+                ..sample
+            });
+        }
+
+        let pdf = self.pdf(outgoing, &sample.incoming);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: self.evaluate(outgoing, &sample.incoming) * (sample.incoming.z.abs() / pdf),
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64 {
+        let total_weight = self.total_weight();
+        self.components
+            .iter()
+            .map(|(weight, bsdf)| weight / total_weight * bsdf.pdf(outgoing, incoming))
+            .sum()
+    }
+
+    fn albedo(&self) -> Vector3D {
+        self.components
+            .iter()
+            .fold(Vector3D::default(), |sum, (weight, bsdf)| {
+                sum + bsdf.albedo() * *weight
+            })
+    }
+}
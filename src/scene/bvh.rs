@@ -0,0 +1,511 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use simd::{BoxTest, Boxes, NodePacket, NodeRay, PacketBoxTest, WIDTH};
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::intersection::Intersection;
+use crate::geometry::ray::Ray;
+use crate::geometry::vector::Vector3D;
+use crate::geometry::ShearedRay;
+use crate::scene::mesh::Mesh;
+use crate::scene::object::Object;
+
+mod simd;
+
+pub use simd::PACKET_SIZE;
+
+/// Most triangles in a leaf
+const MAX_LEAF_SIZE: usize = 4;
+/// Splits are tried between this many buckets of triangle centers along the longest axis
+const BUCKETS: usize = 12;
+/// Cost of visiting a node relative to testing a triangle, for the surface area heuristic
+const TRAVERSAL_COST: f64 = 0.5;
+
+/// Closest surface a ray hits.
+pub type Hit<'a> = (Intersection, Object<'a>);
+
+struct Primitive {
+    triangle: usize,
+    bounds: BoundingBox,
+    centroid: Vector3D,
+}
+
+/// Node of the binary hierarchy the wide one is collapsed from.
+enum BuildNode {
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        children: Box<[BuildNode; 2]>,
+    },
+}
+
+impl BuildNode {
+    fn bounds(&self) -> &BoundingBox {
+        match self {
+            BuildNode::Leaf { bounds, .. } | BuildNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum Child {
+    Empty,
+    Node(u32),
+    /// Range of the hierarchy triangles
+    Leaf {
+        first: u32,
+        count: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct WideNode {
+    boxes: Boxes,
+    children: [Child; WIDTH],
+}
+
+/// Hierarchy of boxes over the scene triangles built by the surface area heuristic,
+/// each node has up to `WIDTH` children whose boxes are tested at once with SIMD if the CPU can.
+#[derive(Serialize, Deserialize)]
+pub struct Bvh {
+    nodes: Vec<WideNode>,
+    root: Child,
+    /// Mesh triangle indices, leaves have ranges of them
+    triangles: Vec<u32>,
+    #[serde(skip, default = "simd::box_test")]
+    box_test: BoxTest,
+    #[serde(skip, default = "simd::packet_box_test")]
+    packet_box_test: PacketBoxTest,
+}
+
+impl Bvh {
+    pub fn new(mesh: &Mesh) -> Self {
+        let mut primitives: Vec<Primitive> = (0..mesh.triangles.len())
+            .map(|triangle| {
+                let bounds = mesh.object(triangle).bounds();
+                Primitive {
+                    triangle,
+                    centroid: bounds.centroid(),
+                    bounds,
+                }
+            })
+            .collect();
+
+        let mut bvh = Self {
+            nodes: vec![],
+            root: Child::Empty,
+            triangles: vec![],
+            box_test: simd::box_test(),
+            packet_box_test: simd::packet_box_test(),
+        };
+        if !primitives.is_empty() {
+            let root = Self::build(&mut primitives, 0);
+            bvh.root = bvh.collapse(root);
+        }
+        bvh.triangles = primitives
+            .iter()
+            .map(|primitive| primitive.triangle as u32)
+            .collect();
+        bvh
+    }
+
+    /// Splits the primitives, which start at `first` in the final order, where the surface area
+    /// heuristic says it is cheapest.
+    fn build(primitives: &mut [Primitive], first: usize) -> BuildNode {
+        let count = primitives.len();
+        let bounds = primitives[1..]
+            .iter()
+            .fold(primitives[0].bounds.clone(), |bounds, primitive| {
+                bounds.union(&primitive.bounds)
+            });
+        let centroid_bounds = primitives[1..].iter().fold(
+            BoundingBox::from_point(&primitives[0].centroid),
+            |centroid_bounds, primitive| centroid_bounds.union_point(&primitive.centroid),
+        );
+        let axis = centroid_bounds.longest_axis();
+        let low = centroid_bounds.min[axis];
+        let extent = centroid_bounds.diagonal()[axis];
+
+        let middle = if count == 1 || (extent == 0.0 && count <= MAX_LEAF_SIZE) {
+            None
+        } else if extent == 0.0 {
+            // All centers coincide, any halves will do
+            Some(count / 2)
+        } else {
+            let bucket = |primitive: &Primitive| {
+                (((primitive.centroid[axis] - low) / extent * BUCKETS as f64) as usize)
+                    .min(BUCKETS - 1)
+            };
+            let mut buckets: [(usize, Option<BoundingBox>); BUCKETS] = Default::default();
+            for primitive in primitives.iter() {
+                let (bucket_count, bucket_bounds) = &mut buckets[bucket(primitive)];
+                *bucket_count += 1;
+                *bucket_bounds = Some(match bucket_bounds {
+                    None => primitive.bounds.clone(),
+                    Some(bucket_bounds) => bucket_bounds.union(&primitive.bounds),
+                });
+            }
+
+            let side_cost = |side: &[(usize, Option<BoundingBox>)]| {
+                let (side_count, side_bounds) = side.iter().fold(
+                    (0, None::<BoundingBox>),
+                    |(side_count, side_bounds), (bucket_count, bucket_bounds)| {
+                        let side_bounds = match (side_bounds, bucket_bounds) {
+                            (Some(a), Some(b)) => Some(a.union(b)),
+                            (a, b) => a.or(b.clone()),
+                        };
+                        (side_count + bucket_count, side_bounds)
+                    },
+                );
+                side_count as f64 * side_bounds.map_or(0.0, |bounds| bounds.surface_area())
+            };
+            let (split, cost) = (1..BUCKETS)
+                .map(|split| {
+                    let cost = TRAVERSAL_COST
+                        + (side_cost(&buckets[..split]) + side_cost(&buckets[split..]))
+                            / bounds.surface_area().max(f64::MIN_POSITIVE);
+                    (split, cost)
+                })
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+
+            if count <= MAX_LEAF_SIZE && cost >= count as f64 {
+                None
+            } else {
+                Some(buckets[..split].iter().map(|(count, _)| count).sum())
+            }
+        };
+
+        let Some(middle) = middle else {
+            return BuildNode::Leaf {
+                bounds,
+                first,
+                count,
+            };
+        };
+        // Bucket order is the order of the centers, so this puts the buckets before the split first
+        primitives
+            .select_nth_unstable_by(middle, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+        let (left, right) = primitives.split_at_mut(middle);
+        BuildNode::Interior {
+            bounds,
+            children: Box::new([Self::build(left, first), Self::build(right, first + middle)]),
+        }
+    }
+
+    /// Pulls the children of the largest interior descendants up into the node until it is full.
+    fn collapse(&mut self, node: BuildNode) -> Child {
+        let children = match node {
+            BuildNode::Leaf { first, count, .. } => {
+                return Child::Leaf {
+                    first: first as u32,
+                    count: count as u32,
+                }
+            }
+            BuildNode::Interior { children, .. } => children,
+        };
+
+        let mut members = Vec::from(*children);
+        while members.len() < WIDTH {
+            let largest = members
+                .iter()
+                .enumerate()
+                .filter(|(_, member)| matches!(member, BuildNode::Interior { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    a.bounds()
+                        .surface_area()
+                        .total_cmp(&b.bounds().surface_area())
+                })
+                .map(|(index, _)| index);
+            let Some(largest) = largest else {
+                break;
+            };
+            if let BuildNode::Interior { children, .. } = members.swap_remove(largest) {
+                members.extend(*children);
+            }
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(WideNode {
+            boxes: Boxes::empty(),
+            children: [Child::Empty; WIDTH],
+        });
+        for (lane, member) in members.into_iter().enumerate() {
+            self.nodes[index].boxes.set(lane, member.bounds());
+            let child = self.collapse(member);
+            self.nodes[index].children[lane] = child;
+        }
+        Child::Node(index as u32)
+    }
+
+    /// Closest hit of the ray that `accept` lets through. It is only asked about hits
+    /// closer than the closest one accepted so far.
+    pub fn closest_hit<'a>(
+        &self,
+        mesh: &'a Mesh,
+        ray: &Ray,
+        mut accept: impl FnMut(&Intersection, &Object) -> bool,
+    ) -> Option<Hit<'a>> {
+        let node_ray = NodeRay::new(ray);
+        let sheared_ray = ShearedRay::new(ray);
+        let mut closest = None;
+        let mut max_distance = f32::INFINITY;
+        // Children to visit and the distances at which the ray enters them
+        let mut stack = Vec::with_capacity(64);
+        stack.push((self.root, 0.0));
+
+        while let Some((child, near)) = stack.pop() {
+            if near > max_distance {
+                continue;
+            }
+            match child {
+                Child::Empty => {}
+                Child::Node(node) => {
+                    let node = &self.nodes[node as usize];
+                    let (distances, mut lanes) =
+                        (self.box_test)(&node.boxes, &node_ray, max_distance);
+                    let start = stack.len();
+                    while lanes != 0 {
+                        let lane = lanes.trailing_zeros() as usize;
+                        lanes &= lanes - 1;
+                        stack.push((node.children[lane], distances[lane]));
+                    }
+                    // Nearest child on top
+                    stack[start..].sort_unstable_by(|(_, a), (_, b)| b.total_cmp(a));
+                }
+                Child::Leaf { first, count } => self.intersect_leaf(
+                    mesh,
+                    first as usize..(first + count) as usize,
+                    &sheared_ray,
+                    &mut closest,
+                    &mut max_distance,
+                    &mut accept,
+                ),
+            }
+        }
+        closest
+    }
+
+    /// Same as `closest_hit` for coherent rays, like camera rays through neighbouring pixels,
+    /// which go through the hierarchy together `PACKET_SIZE` at a time.
+    /// `accept` also gets the index of the ray.
+    pub fn closest_hits<'a>(
+        &self,
+        mesh: &'a Mesh,
+        rays: &[Ray],
+        mut accept: impl FnMut(usize, &Intersection, &Object) -> bool,
+    ) -> Vec<Option<Hit<'a>>> {
+        let mut hits = Vec::with_capacity(rays.len());
+        for (packet, packet_rays) in rays.chunks(PACKET_SIZE).enumerate() {
+            hits.extend(
+                self.trace_packet(mesh, packet_rays, |ray, intersection, object| {
+                    accept(packet * PACKET_SIZE + ray, intersection, object)
+                }),
+            );
+        }
+        hits
+    }
+
+    fn trace_packet<'a>(
+        &self,
+        mesh: &'a Mesh,
+        rays: &[Ray],
+        mut accept: impl FnMut(usize, &Intersection, &Object) -> bool,
+    ) -> Vec<Option<Hit<'a>>> {
+        let packet = NodePacket::new(rays);
+        let sheared_rays: Vec<ShearedRay> = rays.iter().map(ShearedRay::new).collect();
+        let mut closest: Vec<Option<Hit<'a>>> = rays.iter().map(|_| None).collect();
+        let mut max_distances = [f32::INFINITY; PACKET_SIZE];
+        // Children to visit, the mask of the rays that enter them and where they do
+        let mut stack = Vec::with_capacity(64);
+        stack.push((self.root, (1 << rays.len()) - 1, [0.0; PACKET_SIZE]));
+
+        while let Some((child, mask, near)) = stack.pop() {
+            let active = (0..rays.len())
+                .filter(|&ray| mask & 1 << ray != 0 && near[ray] <= max_distances[ray])
+                .fold(0, |active, ray| active | 1 << ray);
+            if active == 0 {
+                continue;
+            }
+            match child {
+                Child::Empty => {}
+                Child::Node(node) => {
+                    let node = &self.nodes[node as usize];
+                    let start = stack.len();
+                    for (lane, &child) in node.children.iter().enumerate() {
+                        if matches!(child, Child::Empty) {
+                            continue;
+                        }
+                        let (distances, entering) = (self.packet_box_test)(
+                            &node.boxes,
+                            lane,
+                            &packet,
+                            &max_distances,
+                            active,
+                        );
+                        if entering != 0 {
+                            stack.push((child, entering, distances));
+                        }
+                    }
+                    // Child some ray enters first on top
+                    let nearest = |(_, mask, near): &(Child, u32, [f32; PACKET_SIZE])| {
+                        (0..PACKET_SIZE)
+                            .filter(|ray| mask & 1 << ray != 0)
+                            .map(|ray| near[ray])
+                            .fold(f32::INFINITY, f32::min)
+                    };
+                    stack[start..].sort_unstable_by(|a, b| nearest(b).total_cmp(&nearest(a)));
+                }
+                Child::Leaf { first, count } => {
+                    for ray in (0..rays.len()).filter(|ray| active & 1 << ray != 0) {
+                        self.intersect_leaf(
+                            mesh,
+                            first as usize..(first + count) as usize,
+                            &sheared_rays[ray],
+                            &mut closest[ray],
+                            &mut max_distances[ray],
+                            |intersection, object| accept(ray, intersection, object),
+                        );
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// Updates the `closest` hit of the ray and the distance to search up to
+    /// with the triangles of the `leaf`.
+    fn intersect_leaf<'a>(
+        &self,
+        mesh: &'a Mesh,
+        leaf: Range<usize>,
+        ray: &ShearedRay,
+        closest: &mut Option<Hit<'a>>,
+        max_distance: &mut f32,
+        mut accept: impl FnMut(&Intersection, &Object) -> bool,
+    ) {
+        for &index in &self.triangles[leaf] {
+            let object = mesh.object(index as usize);
+            let Some(intersection) = object.intersect(ray) else {
+                continue;
+            };
+            let is_closer = closest
+                .as_ref()
+                .is_none_or(|(closest, _)| intersection.distance < closest.distance);
+            if is_closer && accept(&intersection, &object) {
+                *max_distance = (intersection.distance as f32).next_up();
+                *closest = Some((intersection, object));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::scene::Scene;
+
+    fn triangle_soup(rng: &mut impl Rng) -> Scene {
+        let mut obj = String::from("mtllib materials.mtl\nusemtl white\n");
+        for triangle in 0..300 {
+            let center = [(); 3].map(|_| rng.gen_range(-2.0..2.0));
+            for _ in 0..3 {
+                let [x, y, z] = center.map(|c| c + rng.gen_range(-0.3..0.3));
+                obj += &format!("v {} {} {}\n", x, y, z);
+            }
+            let first = 3 * triangle + 1;
+            obj += &format!("f {} {} {}\n", first, first + 1, first + 2);
+        }
+        Scene::from_text(&obj, "newmtl white\nKd 1 1 1\n")
+    }
+
+    /// Closest hit among all triangles with a distance `accept` takes.
+    fn brute_force(mesh: &Mesh, ray: &Ray, accept: impl Fn(f64) -> bool) -> Option<Intersection> {
+        let sheared_ray = ShearedRay::new(ray);
+        (0..mesh.triangles.len())
+            .filter_map(|triangle| mesh.object(triangle).intersect(&sheared_ray))
+            .filter(|intersection| accept(intersection.distance))
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// Rays from the same point in about the same direction, like those of neighbouring pixels,
+    /// and rays from anywhere in any direction.
+    fn rays(rng: &mut impl Rng, count: usize, coherent: bool) -> Vec<Ray> {
+        let from = Vector3D::from([(); 3].map(|_| rng.gen_range(-4.0..4.0)));
+        let towards = Vector3D::from([(); 3].map(|_| rng.gen_range(-1.0..1.0)));
+        (0..count)
+            .map(|_| {
+                let spread = Vector3D::from([(); 3].map(|_| rng.gen_range(-0.2..0.2)));
+                let (from, direction) = if coherent {
+                    (from.clone(), &towards - &from + spread)
+                } else {
+                    (&spread * 20.0, &towards + &spread)
+                };
+                Ray {
+                    from,
+                    direction: direction.normalize(),
+                    inside: false,
+                    differential: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packets_find_the_closest_hits() {
+        let mut rng = rand::thread_rng();
+        let scene = triangle_soup(&mut rng);
+        // Odd rays only take hits past a distance, to check `accept` gets the right ray
+        let accepts = |index: usize, distance: f64| index.is_multiple_of(2) || distance > 3.0;
+        for _ in 0..200 {
+            let coherent = rng.gen();
+            let count = rng.gen_range(1..=3 * PACKET_SIZE);
+            let rays = rays(&mut rng, count, coherent);
+            let hits = scene
+                .bvh
+                .closest_hits(&scene.mesh, &rays, |index, intersection, _| {
+                    accepts(index, intersection.distance)
+                });
+            let packet = &rays[..rays.len().min(PACKET_SIZE)];
+            let packet_hits =
+                scene
+                    .bvh
+                    .trace_packet(&scene.mesh, packet, |index, intersection, _| {
+                        accepts(index, intersection.distance)
+                    });
+            for (index, ray) in rays.iter().enumerate() {
+                let expected = brute_force(&scene.mesh, ray, |distance| accepts(index, distance));
+                let single_hit = scene.bvh.closest_hit(&scene.mesh, ray, |intersection, _| {
+                    accepts(index, intersection.distance)
+                });
+                let found = [
+                    Some(&hits[index]),
+                    packet_hits.get(index),
+                    Some(&single_hit),
+                ];
+                for hit in found.into_iter().flatten() {
+                    match (hit, &expected) {
+                        (None, None) => {}
+                        (Some((hit, _)), Some(expected)) => {
+                            assert_eq!(hit.distance, expected.distance);
+                            assert!(hit.position == expected.position);
+                        }
+                        _ => panic!(
+                            "ray {} hit {}, expected {}",
+                            index,
+                            hit.is_some(),
+                            expected.is_some()
+                        ),
+                    }
+                }
+            }
+        }
+    }
+}
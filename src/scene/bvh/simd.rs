@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::ray::Ray;
+
+/// Children of a node, one box test covers all of them.
+pub const WIDTH: usize = 8;
+/// Rays that go through the hierarchy together, one packet box test covers all of them.
+pub const PACKET_SIZE: usize = 8;
+
+/// Makes up for the rounding of the slab distances, so that boxes touched by the ray
+/// are never missed (Ize 2013).
+const FAR_SCALE: f32 = 1.0 + 2.0 * (3.0 * f32::EPSILON / 2.0) / (1.0 - 3.0 * f32::EPSILON / 2.0);
+
+fn round_down(value: f64) -> f32 {
+    let rounded = value as f32;
+    if rounded as f64 > value {
+        rounded.next_down()
+    } else {
+        rounded
+    }
+}
+
+fn round_up(value: f64) -> f32 {
+    let rounded = value as f32;
+    if (rounded as f64) < value {
+        rounded.next_up()
+    } else {
+        rounded
+    }
+}
+
+/// Boxes of the children of a node, each coordinate of all of them side by side.
+/// They are rounded outwards to `f32`, so far from the origin traversal gets slower, but never misses.
+#[derive(Serialize, Deserialize)]
+#[repr(C, align(32))]
+pub struct Boxes {
+    min: [[f32; WIDTH]; 3],
+    max: [[f32; WIDTH]; 3],
+}
+
+impl Boxes {
+    /// Boxes no ray goes through.
+    pub fn empty() -> Self {
+        Self {
+            min: [[f32::INFINITY; WIDTH]; 3],
+            max: [[f32::NEG_INFINITY; WIDTH]; 3],
+        }
+    }
+
+    pub fn set(&mut self, lane: usize, bounds: &BoundingBox) {
+        for axis in 0..3 {
+            self.min[axis][lane] = round_down(bounds.min[axis]);
+            self.max[axis][lane] = round_up(bounds.max[axis]);
+        }
+    }
+}
+
+/// Ray prepared for box tests. Distances are along the ray like those of intersections.
+pub struct NodeRay {
+    /// Origin rounded so that distances to the near sides of boxes come out shorter
+    near_origin: [f32; 3],
+    /// Origin rounded so that distances to the far sides of boxes come out longer
+    far_origin: [f32; 3],
+    /// Clamped to finite values, so that zero direction components don't lead to NaN
+    inverse_direction: [f32; 3],
+    negative: [bool; 3],
+}
+
+impl NodeRay {
+    pub fn new(ray: &Ray) -> Self {
+        let length = ray.direction.len();
+        let limit = f32::MAX as f64;
+        let negative = [0, 1, 2].map(|axis| ray.direction[axis].is_sign_negative());
+        let (down, up) = (
+            [0, 1, 2].map(|axis| round_down(ray.from[axis])),
+            [0, 1, 2].map(|axis| round_up(ray.from[axis])),
+        );
+        Self {
+            near_origin: [0, 1, 2].map(|axis| if negative[axis] { down[axis] } else { up[axis] }),
+            far_origin: [0, 1, 2].map(|axis| if negative[axis] { up[axis] } else { down[axis] }),
+            inverse_direction: [0, 1, 2]
+                .map(|axis| (length / ray.direction[axis]).clamp(-limit, limit) as f32),
+            negative,
+        }
+    }
+
+    /// Sides of the boxes along `axis` the ray enters and leaves through.
+    fn sides<'a>(&self, boxes: &'a Boxes, axis: usize) -> (&'a [f32; WIDTH], &'a [f32; WIDTH]) {
+        if self.negative[axis] {
+            (&boxes.max[axis], &boxes.min[axis])
+        } else {
+            (&boxes.min[axis], &boxes.max[axis])
+        }
+    }
+}
+
+/// Rays of a packet prepared for box tests like `NodeRay`, each value of all of them side by side.
+/// The signs of the inverse directions tell which sides of the boxes the rays enter through.
+pub struct NodePacket {
+    near_origin: [[f32; PACKET_SIZE]; 3],
+    far_origin: [[f32; PACKET_SIZE]; 3],
+    inverse_direction: [[f32; PACKET_SIZE]; 3],
+}
+
+impl NodePacket {
+    /// Lanes past the rays are left to be masked out.
+    pub fn new(rays: &[Ray]) -> Self {
+        let mut packet = Self {
+            near_origin: [[0.0; PACKET_SIZE]; 3],
+            far_origin: [[0.0; PACKET_SIZE]; 3],
+            inverse_direction: [[0.0; PACKET_SIZE]; 3],
+        };
+        for (lane, ray) in rays.iter().enumerate() {
+            let ray = NodeRay::new(ray);
+            for axis in 0..3 {
+                packet.near_origin[axis][lane] = ray.near_origin[axis];
+                packet.far_origin[axis][lane] = ray.far_origin[axis];
+                packet.inverse_direction[axis][lane] = ray.inverse_direction[axis];
+            }
+        }
+        packet
+    }
+}
+
+/// Distances at which the ray enters the boxes and the mask of the boxes
+/// it goes through before `max_distance`.
+pub type BoxTest = fn(&Boxes, &NodeRay, f32) -> ([f32; WIDTH], u32);
+
+/// Distances at which the rays of a packet enter the box in a lane and the mask of the rays
+/// among `active` that go through it before their `max_distances`.
+pub type PacketBoxTest =
+    fn(&Boxes, usize, &NodePacket, &[f32; PACKET_SIZE], u32) -> ([f32; PACKET_SIZE], u32);
+
+/// Fastest box test the CPU supports.
+pub fn box_test() -> BoxTest {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx") {
+        return intersect_avx;
+    }
+    intersect_scalar
+}
+
+/// Fastest packet box test the CPU supports.
+pub fn packet_box_test() -> PacketBoxTest {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx") {
+        return intersect_packet_avx;
+    }
+    intersect_packet_scalar
+}
+
+fn intersect_scalar(boxes: &Boxes, ray: &NodeRay, max_distance: f32) -> ([f32; WIDTH], u32) {
+    let mut near = [0.0f32; WIDTH];
+    let mut far = [max_distance; WIDTH];
+    for axis in 0..3 {
+        let (near_sides, far_sides) = ray.sides(boxes, axis);
+        let inverse_direction = ray.inverse_direction[axis];
+        for lane in 0..WIDTH {
+            near[lane] =
+                near[lane].max((near_sides[lane] - ray.near_origin[axis]) * inverse_direction);
+            far[lane] = far[lane]
+                .min((far_sides[lane] - ray.far_origin[axis]) * inverse_direction * FAR_SCALE);
+        }
+    }
+
+    let mask = (0..WIDTH)
+        .filter(|&lane| near[lane] <= far[lane])
+        .fold(0, |mask, lane| mask | 1 << lane);
+    (near, mask)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn intersect_avx(boxes: &Boxes, ray: &NodeRay, max_distance: f32) -> ([f32; WIDTH], u32) {
+    // SAFETY: `box_test` only hands this out on CPUs with AVX
+    unsafe { intersect_avx_unchecked(boxes, ray, max_distance) }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn intersect_avx_unchecked(
+    boxes: &Boxes,
+    ray: &NodeRay,
+    max_distance: f32,
+) -> ([f32; WIDTH], u32) {
+    use std::arch::x86_64::*;
+
+    let mut near = _mm256_setzero_ps();
+    let mut far = _mm256_set1_ps(max_distance);
+    for axis in 0..3 {
+        let (near_sides, far_sides) = ray.sides(boxes, axis);
+        let inverse_direction = _mm256_set1_ps(ray.inverse_direction[axis]);
+        let near_axis = _mm256_mul_ps(
+            _mm256_sub_ps(
+                _mm256_loadu_ps(near_sides.as_ptr()),
+                _mm256_set1_ps(ray.near_origin[axis]),
+            ),
+            inverse_direction,
+        );
+        let far_axis = _mm256_mul_ps(
+            _mm256_mul_ps(
+                _mm256_sub_ps(
+                    _mm256_loadu_ps(far_sides.as_ptr()),
+                    _mm256_set1_ps(ray.far_origin[axis]),
+                ),
+                inverse_direction,
+            ),
+            _mm256_set1_ps(FAR_SCALE),
+        );
+        near = _mm256_max_ps(near, near_axis);
+        far = _mm256_min_ps(far, far_axis);
+    }
+
+    let mut distances = [0.0; WIDTH];
+    _mm256_storeu_ps(distances.as_mut_ptr(), near);
+    let mask = _mm256_movemask_ps(_mm256_cmp_ps::<_CMP_LE_OQ>(near, far)) as u32;
+    (distances, mask)
+}
+
+fn intersect_packet_scalar(
+    boxes: &Boxes,
+    lane: usize,
+    packet: &NodePacket,
+    max_distances: &[f32; PACKET_SIZE],
+    active: u32,
+) -> ([f32; PACKET_SIZE], u32) {
+    let mut near = [0.0f32; PACKET_SIZE];
+    let mut far = *max_distances;
+    for axis in 0..3 {
+        let (min, max) = (boxes.min[axis][lane], boxes.max[axis][lane]);
+        for ray in 0..PACKET_SIZE {
+            let inverse_direction = packet.inverse_direction[axis][ray];
+            let (near_side, far_side) = if inverse_direction.is_sign_negative() {
+                (max, min)
+            } else {
+                (min, max)
+            };
+            near[ray] =
+                near[ray].max((near_side - packet.near_origin[axis][ray]) * inverse_direction);
+            far[ray] = far[ray]
+                .min((far_side - packet.far_origin[axis][ray]) * inverse_direction * FAR_SCALE);
+        }
+    }
+
+    let mask = (0..PACKET_SIZE)
+        .filter(|&ray| near[ray] <= far[ray])
+        .fold(0, |mask, ray| mask | 1 << ray);
+    (near, mask & active)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn intersect_packet_avx(
+    boxes: &Boxes,
+    lane: usize,
+    packet: &NodePacket,
+    max_distances: &[f32; PACKET_SIZE],
+    active: u32,
+) -> ([f32; PACKET_SIZE], u32) {
+    // SAFETY: `packet_box_test` only hands this out on CPUs with AVX
+    unsafe { intersect_packet_avx_unchecked(boxes, lane, packet, max_distances, active) }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn intersect_packet_avx_unchecked(
+    boxes: &Boxes,
+    lane: usize,
+    packet: &NodePacket,
+    max_distances: &[f32; PACKET_SIZE],
+    active: u32,
+) -> ([f32; PACKET_SIZE], u32) {
+    use std::arch::x86_64::*;
+
+    let mut near = _mm256_setzero_ps();
+    let mut far = _mm256_loadu_ps(max_distances.as_ptr());
+    for axis in 0..3 {
+        let min = _mm256_set1_ps(boxes.min[axis][lane]);
+        let max = _mm256_set1_ps(boxes.max[axis][lane]);
+        let inverse_direction = _mm256_loadu_ps(packet.inverse_direction[axis].as_ptr());
+        // Rays going down the axis enter through the max side
+        let near_side = _mm256_blendv_ps(min, max, inverse_direction);
+        let far_side = _mm256_blendv_ps(max, min, inverse_direction);
+        let near_axis = _mm256_mul_ps(
+            _mm256_sub_ps(
+                near_side,
+                _mm256_loadu_ps(packet.near_origin[axis].as_ptr()),
+            ),
+            inverse_direction,
+        );
+        let far_axis = _mm256_mul_ps(
+            _mm256_mul_ps(
+                _mm256_sub_ps(far_side, _mm256_loadu_ps(packet.far_origin[axis].as_ptr())),
+                inverse_direction,
+            ),
+            _mm256_set1_ps(FAR_SCALE),
+        );
+        near = _mm256_max_ps(near, near_axis);
+        far = _mm256_min_ps(far, far_axis);
+    }
+
+    let mut distances = [0.0; PACKET_SIZE];
+    _mm256_storeu_ps(distances.as_mut_ptr(), near);
+    let mask = _mm256_movemask_ps(_mm256_cmp_ps::<_CMP_LE_OQ>(near, far)) as u32;
+    (distances, mask & active)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::geometry::vector::Vector3D;
+
+    fn random_boxes(rng: &mut impl Rng) -> Boxes {
+        let mut boxes = Boxes::empty();
+        // The last lane stays empty
+        for lane in 0..WIDTH - 1 {
+            let corners: [Vector3D; 2] =
+                [(); 2].map(|_| Vector3D::from([(); 3].map(|_| rng.gen_range(-2.0..2.0))));
+            let bounds = BoundingBox::from_point(&corners[0]).union_point(&corners[1]);
+            boxes.set(lane, &bounds);
+        }
+        boxes
+    }
+
+    fn random_ray(rng: &mut impl Rng) -> Ray {
+        let mut direction = [(); 3].map(|_| rng.gen_range(-1.0..1.0));
+        // Rays along the sides of boxes too
+        if rng.gen_bool(0.2) {
+            direction[rng.gen_range(0..3)] = 0.0;
+        }
+        Ray {
+            from: Vector3D::from([(); 3].map(|_| rng.gen_range(-3.0..3.0))),
+            direction: Vector3D::from(direction).normalize(),
+            inside: false,
+            differential: None,
+        }
+    }
+
+    #[test]
+    fn packet_box_tests_agree_with_single_rays() {
+        let mut tests: Vec<PacketBoxTest> = vec![intersect_packet_scalar];
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx") {
+            tests.push(intersect_packet_avx);
+        }
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let boxes = random_boxes(&mut rng);
+            let rays: Vec<Ray> = (0..rng.gen_range(1..=PACKET_SIZE))
+                .map(|_| random_ray(&mut rng))
+                .collect();
+            let max_distances = [(); PACKET_SIZE].map(|_| rng.gen_range(0.5..8.0));
+            let active = rng.gen_range(0..1 << rays.len());
+            let packet = NodePacket::new(&rays);
+            for lane in 0..WIDTH {
+                for test in &tests {
+                    let (distances, mask) = test(&boxes, lane, &packet, &max_distances, active);
+                    for (index, ray) in rays.iter().enumerate() {
+                        let (ray_distances, lanes) =
+                            intersect_scalar(&boxes, &NodeRay::new(ray), max_distances[index]);
+                        let hit = lanes & 1 << lane != 0 && active & 1 << index != 0;
+                        assert_eq!(mask & 1 << index != 0, hit);
+                        if hit {
+                            assert_eq!(distances[index], ray_distances[lane]);
+                        }
+                    }
+                    assert_eq!(mask >> rays.len(), 0);
+                }
+            }
+        }
+    }
+}
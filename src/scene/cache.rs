@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::scene::bvh::Bvh;
+use crate::scene::mesh::{Mesh, PositionFloat};
+
+const MAGIC: [u8; 4] = *b"RTSC";
+/// Bumped whenever the layout of the cached data changes.
+const VERSION: u32 = 3;
+/// Caches are only read by builds that store positions in the same precision.
+const POSITION_BYTES: u32 = std::mem::size_of::<PositionFloat>() as u32;
+
+/// Binary file with the parsed geometry of a scene, and optionally its hierarchy,
+/// reused while the .obj and its .mtl files stay the same.
+pub struct SceneCache {
+    pub path: PathBuf,
+    pub include_bvh: bool,
+}
+
+/// Contents of an up to date cache.
+pub struct CachedScene {
+    /// Lines of the .obj besides the geometry with their indices, to be read again
+    pub directives: Vec<(usize, String)>,
+    /// Geometry without materials, they come from the directives
+    pub mesh: Mesh,
+    /// Only read when the cache is asked for it
+    pub bvh: Option<Bvh>,
+    /// Whether the cache should be written again, as it holds a hierarchy
+    /// when it isn't asked for one or the other way around
+    pub needs_rewrite: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct SourceFile {
+    path: PathBuf,
+    length: u64,
+    modified: SystemTime,
+    hash: u32,
+}
+
+impl SourceFile {
+    fn new(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mut file = File::open(path)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buffer = vec![0; 1 << 20];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        Ok(Self {
+            path: path.canonicalize()?,
+            length: metadata.len(),
+            modified: metadata.modified()?,
+            hash: hasher.finalize(),
+        })
+    }
+
+    /// Whether the file is still the same, cheap checks first.
+    fn is_current(&self) -> bool {
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return false;
+        };
+        metadata.len() == self.length
+            && metadata.modified().ok() == Some(self.modified)
+            && SourceFile::new(&self.path).is_ok_and(|current| current == *self)
+    }
+}
+
+impl SceneCache {
+    /// Cached scene of the .obj at `scene_path`, if the cache exists and is up to date.
+    pub fn load(&self, scene_path: &Path) -> Option<CachedScene> {
+        match self.try_load(scene_path) {
+            Ok(cached) => {
+                info!("Read scene geometry from cache {}", self.path.display());
+                Some(cached)
+            }
+            Err(err) => {
+                info!("Not using scene cache {}: {}", self.path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn try_load(&self, scene_path: &Path) -> Result<CachedScene> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let (magic, version, position_bytes): ([u8; 4], u32, u32) =
+            bincode::deserialize_from(&mut reader)?;
+        if magic != MAGIC || version != VERSION || position_bytes != POSITION_BYTES {
+            return Err(anyhow!("not a cache of this version"));
+        }
+        let sources: Vec<SourceFile> = bincode::deserialize_from(&mut reader)?;
+        if sources.first().map(|source| &source.path) != Some(&scene_path.canonicalize()?) {
+            return Err(anyhow!("made for another scene"));
+        }
+        if !sources.iter().all(SourceFile::is_current) {
+            return Err(anyhow!("scene files changed"));
+        }
+
+        let directives = bincode::deserialize_from(&mut reader)?;
+        let mesh = bincode::deserialize_from(&mut reader)?;
+        let has_bvh: bool = bincode::deserialize_from(&mut reader)?;
+        let bvh = if has_bvh && self.include_bvh {
+            Some(bincode::deserialize_from(&mut reader)?)
+        } else {
+            None
+        };
+        Ok(CachedScene {
+            directives,
+            mesh,
+            bvh,
+            needs_rewrite: has_bvh != self.include_bvh,
+        })
+    }
+
+    /// Writes the cache, failing only with a warning since the scene is already read.
+    /// It is written next to the old one and renamed over it, so that an interrupted
+    /// write never leaves a broken cache behind.
+    pub fn store(
+        &self,
+        scene_path: &Path,
+        material_files: &[PathBuf],
+        directives: &[(usize, String)],
+        mesh: &Mesh,
+        bvh: &Bvh,
+    ) {
+        match self.try_store(scene_path, material_files, directives, mesh, bvh) {
+            Ok(()) => info!("Wrote scene cache {}", self.path.display()),
+            Err(err) => warn!(
+                "Couldn't write scene cache {}: {}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+
+    fn try_store(
+        &self,
+        scene_path: &Path,
+        material_files: &[PathBuf],
+        directives: &[(usize, String)],
+        mesh: &Mesh,
+        bvh: &Bvh,
+    ) -> Result<()> {
+        let sources = std::iter::once(scene_path)
+            .chain(material_files.iter().map(PathBuf::as_path))
+            .map(SourceFile::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut temporary_name = self.path.file_name().unwrap_or_default().to_owned();
+        temporary_name.push(format!(".{}.tmp", std::process::id()));
+        let temporary_path = self.path.with_file_name(temporary_name);
+        let written = (|| {
+            let mut writer = BufWriter::new(File::create(&temporary_path)?);
+            bincode::serialize_into(&mut writer, &(MAGIC, VERSION, POSITION_BYTES))?;
+            bincode::serialize_into(&mut writer, &sources)?;
+            bincode::serialize_into(&mut writer, directives)?;
+            bincode::serialize_into(&mut writer, mesh)?;
+            bincode::serialize_into(&mut writer, &self.include_bvh)?;
+            if self.include_bvh {
+                bincode::serialize_into(&mut writer, bvh)?;
+            }
+            writer.into_inner()?.sync_all()?;
+            std::fs::rename(&temporary_path, &self.path)?;
+            Ok(())
+        })();
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temporary_path);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Scene;
+
+    #[test]
+    fn cache_is_rewritten_to_match_the_options() {
+        let directory =
+            std::env::temp_dir().join(format!("raytracer22-cache-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let scene_path = directory.join("scene.obj");
+        std::fs::write(&scene_path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let cache = |include_bvh| SceneCache {
+            path: directory.join("scene.cache"),
+            include_bvh,
+        };
+        let contents = |include_bvh| {
+            let cached = cache(include_bvh).try_load(&scene_path).unwrap();
+            (cached.bvh.is_some(), cached.needs_rewrite)
+        };
+
+        Scene::try_read(&scene_path, Some(&cache(false))).unwrap();
+        assert_eq!(contents(false), (false, false));
+        // The hierarchy is missing, so it is built and written back
+        assert_eq!(contents(true), (false, true));
+        Scene::try_read(&scene_path, Some(&cache(true))).unwrap();
+        assert_eq!(contents(true), (true, false));
+        // It isn't read when it isn't asked for, and dropped from the cache
+        assert_eq!(contents(false), (false, true));
+        Scene::try_read(&scene_path, Some(&cache(false))).unwrap();
+        assert_eq!(contents(false), (false, false));
+
+        let mut files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["scene.cache", "scene.obj"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
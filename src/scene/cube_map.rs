@@ -1,10 +1,13 @@
 use std::path::Path;
+use std::str::FromStr;
 
-use image::RgbImage;
+use anyhow::{anyhow, Result};
+use image::Rgb32FImage;
 
 use crate::geometry::ray::Ray;
 use crate::geometry::vector::Vector3D;
 
+#[derive(Clone, Copy)]
 pub enum StrongestDirection {
     Front,
     Back,
@@ -14,8 +17,24 @@ pub enum StrongestDirection {
     Left,
 }
 
+impl StrongestDirection {
+    /// Order of faces in strips and in the list of separate face files: +x, -x, +y, -y, +z, -z
+    const ALL: [StrongestDirection; 6] = [
+        StrongestDirection::Front,
+        StrongestDirection::Back,
+        StrongestDirection::Top,
+        StrongestDirection::Bottom,
+        StrongestDirection::Right,
+        StrongestDirection::Left,
+    ];
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
 fn get_strongest_direction(vector: &Vector3D) -> StrongestDirection {
-    let abs = vec![vector.x.abs(), vector.y.abs(), vector.z.abs()];
+    let abs = [vector.x.abs(), vector.y.abs(), vector.z.abs()];
 
     match abs
         .iter()
@@ -34,77 +53,230 @@ fn get_strongest_direction(vector: &Vector3D) -> StrongestDirection {
     }
 }
 
-pub struct CubeMap {
-    img: RgbImage,
+/// Projects a direction onto its cube face, `u` and `v` are in [0, 1]
+/// with the face orientations of the horizontal cross.
+fn direction_to_face_uv(direction: &Vector3D) -> (StrongestDirection, f64, f64) {
+    let face = get_strongest_direction(direction);
+    let (u, v) = match face {
+        StrongestDirection::Front => {
+            let scaled_direction = direction / direction.x;
+            (scaled_direction.z, -scaled_direction.y)
+        }
+        StrongestDirection::Back => {
+            let scaled_direction = direction / -direction.x;
+            (-scaled_direction.z, -scaled_direction.y)
+        }
+        StrongestDirection::Top => {
+            let scaled_direction = direction / direction.y;
+            (scaled_direction.z, scaled_direction.x)
+        }
+        StrongestDirection::Bottom => {
+            let scaled_direction = direction / -direction.y;
+            (scaled_direction.z, -scaled_direction.x)
+        }
+        StrongestDirection::Right => {
+            let scaled_direction = direction / direction.z;
+            (-scaled_direction.x, -scaled_direction.y)
+        }
+        StrongestDirection::Left => {
+            let scaled_direction = direction / -direction.z;
+            (scaled_direction.x, -scaled_direction.y)
+        }
+    };
+    (face, (1.0 + u) / 2.0, (1.0 + v) / 2.0)
 }
 
-impl CubeMap {
-    pub fn new(image_path: &Path) -> Self {
-        CubeMap {
-            img: image::open(image_path)
-                .unwrap_or_else(|_| panic!("Couldn't open cube map {}", image_path.display()))
-                .into_rgb8(),
+/// Inverse of `direction_to_face_uv`, works for `u` and `v` slightly outside of the face too.
+fn face_uv_to_direction(face: StrongestDirection, u: f64, v: f64) -> Vector3D {
+    let u = 2.0 * u - 1.0;
+    let v = 2.0 * v - 1.0;
+    match face {
+        StrongestDirection::Front => Vector3D::from([1.0, -v, u]),
+        StrongestDirection::Back => Vector3D::from([-1.0, -v, -u]),
+        StrongestDirection::Top => Vector3D::from([v, 1.0, u]),
+        StrongestDirection::Bottom => Vector3D::from([-v, -1.0, u]),
+        StrongestDirection::Right => Vector3D::from([-u, -v, 1.0]),
+        StrongestDirection::Left => Vector3D::from([u, -v, -1.0]),
+    }
+}
+
+/// How the six faces are packed into images.
+#[derive(Clone, Copy)]
+pub enum CubeMapLayout {
+    /// 4×3, the middle row is left, front, right, back
+    HorizontalCross,
+    /// 3×4, the middle column is top, front, bottom, back (upside down),
+    /// left and right are next to the front
+    VerticalCross,
+    /// 6×1, faces in the +x, -x, +y, -y, +z, -z order
+    Strip,
+    /// Six images in the +x, -x, +y, -y, +z, -z order
+    Separate,
+}
+
+impl FromStr for CubeMapLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "horizontal_cross" => Ok(CubeMapLayout::HorizontalCross),
+            "vertical_cross" => Ok(CubeMapLayout::VerticalCross),
+            "strip" => Ok(CubeMapLayout::Strip),
+            "separate" => Ok(CubeMapLayout::Separate),
+            _ => Err(anyhow!("Unknown cube map layout: {}", s)),
         }
     }
+}
 
-    pub fn trace(&self, ray: &Ray) -> Vector3D {
-        let side_size = (self.img.width() / 4) as usize;
-        let direction = &ray.direction;
-
-        let x: u32;
-        let y: u32;
-        match get_strongest_direction(direction) {
-            StrongestDirection::Front => {
-                let scaled_direction = direction / direction.x;
-                x = (3. / 2. * side_size as f64 - side_size as f64 * -scaled_direction.z / 2.0)
-                    as u32;
-                y = (3. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.y / 2.0)
-                    as u32;
-            }
-            StrongestDirection::Back => {
-                let scaled_direction = direction / -direction.x;
-                x = (7. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.z / 2.0)
-                    as u32;
-                y = (3. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.y / 2.0)
-                    as u32;
-            }
-            StrongestDirection::Top => {
-                let scaled_direction = direction / direction.y;
-                x = (3. / 2. * side_size as f64 + side_size as f64 * scaled_direction.z / 2.0)
-                    as u32;
-                y = (1. / 2. * side_size as f64 + side_size as f64 * scaled_direction.x / 2.0)
-                    as u32;
-            }
-            StrongestDirection::Bottom => {
-                let scaled_direction = direction / -direction.y;
-                x = (3. / 2. * side_size as f64 + side_size as f64 * scaled_direction.z / 2.0)
-                    as u32;
-                y = (5. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.x / 2.0)
-                    as u32
-                    - 1;
-            }
-            StrongestDirection::Right => {
-                let scaled_direction = direction / direction.z;
-                x = (5. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.x / 2.0)
-                    as u32;
-                y = (3. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.y / 2.0)
-                    as u32;
-            }
-            StrongestDirection::Left => {
-                let scaled_direction = direction / -direction.z;
-                x = (1. / 2. * side_size as f64 + side_size as f64 * scaled_direction.x / 2.0)
-                    as u32;
-                y = (3. / 2. * side_size as f64 + side_size as f64 * -scaled_direction.y / 2.0)
-                    as u32;
+impl CubeMapLayout {
+    /// Cell of a face in a single image layout, in face sizes, and whether it is upside down.
+    fn cell(&self, face: StrongestDirection) -> (u32, u32, bool) {
+        match self {
+            CubeMapLayout::HorizontalCross => match face {
+                StrongestDirection::Front => (1, 1, false),
+                StrongestDirection::Back => (3, 1, false),
+                StrongestDirection::Top => (1, 0, false),
+                StrongestDirection::Bottom => (1, 2, false),
+                StrongestDirection::Right => (2, 1, false),
+                StrongestDirection::Left => (0, 1, false),
+            },
+            CubeMapLayout::VerticalCross => match face {
+                StrongestDirection::Front => (1, 1, false),
+                StrongestDirection::Back => (1, 3, true),
+                StrongestDirection::Top => (1, 0, false),
+                StrongestDirection::Bottom => (1, 2, false),
+                StrongestDirection::Right => (2, 1, false),
+                StrongestDirection::Left => (0, 1, false),
+            },
+            CubeMapLayout::Strip | CubeMapLayout::Separate => (face.index() as u32, 0, false),
+        }
+    }
+
+    fn cells_across(&self) -> u32 {
+        match self {
+            CubeMapLayout::HorizontalCross => 4,
+            CubeMapLayout::VerticalCross => 3,
+            CubeMapLayout::Strip => 6,
+            CubeMapLayout::Separate => 1,
+        }
+    }
+
+    fn cells_down(&self) -> u32 {
+        match self {
+            CubeMapLayout::HorizontalCross => 3,
+            CubeMapLayout::VerticalCross => 4,
+            CubeMapLayout::Strip | CubeMapLayout::Separate => 1,
+        }
+    }
+}
+
+fn open_image(image_path: &Path) -> Rgb32FImage {
+    image::open(image_path)
+        .unwrap_or_else(|_| panic!("Couldn't open cube map {}", image_path.display()))
+        .into_rgb32f()
+}
+
+fn read_face(image: &Rgb32FImage, x: u32, y: u32, size: u32, upside_down: bool) -> Vec<Vector3D> {
+    let mut face = Vec::with_capacity((size * size) as usize);
+    for j in 0..size {
+        for i in 0..size {
+            let (i, j) = if upside_down {
+                (size - 1 - i, size - 1 - j)
+            } else {
+                (i, j)
+            };
+            let color = image.get_pixel(x * size + i, y * size + j).0;
+            face.push(Vector3D {
+                x: color[0] as f64,
+                y: color[1] as f64,
+                z: color[2] as f64,
+            });
+        }
+    }
+    face
+}
+
+pub struct CubeMap {
+    size: usize,
+    faces: Vec<Vec<Vector3D>>,
+}
+
+impl CubeMap {
+    /// `image_paths` holds one image, or six for the separate layout.
+    /// All faces must be square and of the same size.
+    pub fn new(layout: CubeMapLayout, image_paths: &[&Path]) -> Result<Self> {
+        let images: Vec<Rgb32FImage> = image_paths.iter().map(|path| open_image(path)).collect();
+        let size = images[0].width() / layout.cells_across();
+        for (image, path) in images.iter().zip(image_paths) {
+            if size == 0
+                || image.width() != size * layout.cells_across()
+                || image.height() != size * layout.cells_down()
+            {
+                return Err(anyhow!(
+                    "{} is {}x{} pixels, but the cube map needs {}x{} for square faces of the same size",
+                    path.display(),
+                    image.width(),
+                    image.height(),
+                    size.max(1) * layout.cells_across(),
+                    size.max(1) * layout.cells_down()
+                ));
             }
         }
 
-        let color = self.img.get_pixel(x, y).0;
+        let faces = match layout {
+            CubeMapLayout::Separate => images
+                .iter()
+                .map(|image| read_face(image, 0, 0, size, false))
+                .collect(),
+            _ => StrongestDirection::ALL
+                .iter()
+                .map(|face| {
+                    let (x, y, upside_down) = layout.cell(*face);
+                    read_face(&images[0], x, y, size, upside_down)
+                })
+                .collect(),
+        };
+
+        Ok(CubeMap {
+            size: size as usize,
+            faces,
+        })
+    }
 
-        Vector3D {
-            x: color[0] as f64 / 256.,
-            y: color[1] as f64 / 256.,
-            z: color[2] as f64 / 256.,
+    /// Texel by integer coordinates, which may step one texel over the face border.
+    /// Those are looked up on the neighbouring face, so filtering is seamless.
+    fn texel(&self, face: StrongestDirection, i: isize, j: isize) -> &Vector3D {
+        let size = self.size as isize;
+        if (0..size).contains(&i) && (0..size).contains(&j) {
+            return &self.faces[face.index()][(i + size * j) as usize];
         }
+
+        let direction = face_uv_to_direction(
+            face,
+            (i as f64 + 0.5) / self.size as f64,
+            (j as f64 + 0.5) / self.size as f64,
+        );
+        let (face, u, v) = direction_to_face_uv(&direction);
+        let i = ((u * self.size as f64) as usize).min(self.size - 1);
+        let j = ((v * self.size as f64) as usize).min(self.size - 1);
+        &self.faces[face.index()][i + self.size * j]
+    }
+
+    pub fn trace(&self, ray: &Ray) -> Vector3D {
+        let (face, u, v) = direction_to_face_uv(&ray.direction);
+
+        // Bilinear filtering between the four closest texel centers
+        let x = u * self.size as f64 - 0.5;
+        let y = v * self.size as f64 - 0.5;
+        let i = x.floor();
+        let j = y.floor();
+        let dx = x - i;
+        let dy = y - j;
+        let (i, j) = (i as isize, j as isize);
+
+        self.texel(face, i, j) * ((1.0 - dx) * (1.0 - dy))
+            + self.texel(face, i + 1, j) * (dx * (1.0 - dy))
+            + self.texel(face, i, j + 1) * ((1.0 - dx) * dy)
+            + self.texel(face, i + 1, j + 1) * (dx * dy)
     }
 }
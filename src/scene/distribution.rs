@@ -0,0 +1,108 @@
+/// Piecewise constant distribution over [0, 1), sampled by inverting its CDF.
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f64>) -> Self {
+        let n = function.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for value in function.iter() {
+            cdf.push(cdf.last().unwrap() + value.abs() / n as f64);
+        }
+
+        let integral = cdf[n];
+        if integral == 0.0 {
+            // Nothing to prefer, sample uniformly
+            for (i, value) in cdf.iter_mut().enumerate() {
+                *value = i as f64 / n as f64;
+            }
+        } else {
+            for value in cdf.iter_mut() {
+                *value /= integral;
+            }
+        }
+
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.function.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Returns a point in [0, 1), its density and the index of the segment it is in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let offset = (self.cdf.partition_point(|value| *value <= u) - 1).min(self.len() - 1);
+        let segment = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if segment > 0.0 {
+            (u - self.cdf[offset]) / segment
+        } else {
+            0.0
+        };
+        (
+            (offset as f64 + du) / self.len() as f64,
+            self.segment_pdf(offset),
+            offset,
+        )
+    }
+
+    /// Density of `sample` returning `x`.
+    pub fn pdf(&self, x: f64) -> f64 {
+        self.segment_pdf(self.offset(x))
+    }
+
+    fn offset(&self, x: f64) -> usize {
+        ((x * self.len() as f64) as usize).min(self.len() - 1)
+    }
+
+    pub fn segment_pdf(&self, offset: usize) -> f64 {
+        if self.integral == 0.0 {
+            1.0
+        } else {
+            self.function[offset].abs() / self.integral
+        }
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)^2, `function` is given row by row.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f64], width: usize) -> Self {
+        let conditional: Vec<Distribution1D> = function
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Returns a point `(u, v)` and its density, `v` selects the row.
+    pub fn sample(&self, u1: f64, u2: f64) -> (f64, f64, f64) {
+        let (v, marginal_pdf, row) = self.marginal.sample(u2);
+        let (u, conditional_pdf, _) = self.conditional[row].sample(u1);
+        (u, v, marginal_pdf * conditional_pdf)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = self.marginal.offset(v);
+        self.marginal.segment_pdf(row) * self.conditional[row].pdf(u)
+    }
+}
@@ -0,0 +1,119 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::ray::Ray;
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::luminance;
+use crate::scene::distribution::Distribution2D;
+use crate::scene::sky::SkySample;
+
+/// High dynamic range latitude-longitude (equirectangular) environment map, +y is up.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    data: Vec<Vector3D>,
+    /// Rotation around the vertical axis, radians
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image_path: &Path, rotation_degrees: f64, intensity: f64) -> Result<Self> {
+        let image = match image::open(image_path) {
+            Ok(image) => image.into_rgb32f(),
+            Err(err) => {
+                return Err(anyhow!(
+                    "Couldn't open environment map {}: {}",
+                    image_path.display(),
+                    err
+                ))
+            }
+        };
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+        let data: Vec<Vector3D> = image
+            .pixels()
+            .map(|pixel| Vector3D {
+                x: pixel.0[0] as f64,
+                y: pixel.0[1] as f64,
+                z: pixel.0[2] as f64,
+            })
+            .collect();
+
+        // Rows near the poles cover less of the sphere
+        let weights: Vec<f64> = data
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                let theta = ((i / width) as f64 + 0.5) / height as f64 * PI;
+                luminance(color) * theta.sin()
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            data,
+            rotation: rotation_degrees.to_radians(),
+            intensity,
+            distribution: Distribution2D::new(&weights, width),
+        })
+    }
+
+    fn direction_to_uv(&self, direction: &Vector3D) -> (f64, f64) {
+        let phi = (direction.z.atan2(direction.x) + self.rotation).rem_euclid(2.0 * PI);
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vector3D {
+        let phi = u * 2.0 * PI - self.rotation;
+        let theta = v * PI;
+        Vector3D {
+            x: theta.sin() * phi.cos(),
+            y: theta.cos(),
+            z: theta.sin() * phi.sin(),
+        }
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Vector3D {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        &self.data[x + self.width * y] * self.intensity
+    }
+
+    pub fn trace(&self, ray: &Ray) -> Vector3D {
+        let (u, v) = self.direction_to_uv(&ray.direction);
+        self.lookup(u, v)
+    }
+
+    /// Picks a direction proportionally to the luminance of the map.
+    pub fn sample(&self, rng: &mut ThreadRng) -> Option<SkySample> {
+        let (u, v, uv_pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        let sin_theta = (v * PI).sin();
+        if uv_pdf == 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        Some(SkySample {
+            direction: self.uv_to_direction(u, v),
+            radiance: self.lookup(u, v),
+            pdf: uv_pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    /// Density of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Vector3D) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -1,6 +1,72 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use rand::rngs::ThreadRng;
+
 use crate::geometry::vector::Vector3D;
+use crate::scene::light::bounds::LightBounds;
+
+pub mod area;
+pub mod bounds;
+pub mod bvh;
+pub mod directional;
+pub mod point;
+pub mod spot;
+
+/// How the light of a positional light falls off with distance.
+#[derive(Clone, Copy, Default)]
+pub enum Attenuation {
+    #[default]
+    None,
+    Linear,
+    InverseSquare,
+}
+
+impl Attenuation {
+    pub fn factor(&self, distance: f64) -> f64 {
+        match self {
+            Attenuation::None => 1.0,
+            Attenuation::Linear => 1.0 / distance,
+            Attenuation::InverseSquare => 1.0 / (distance * distance),
+        }
+    }
+}
+
+impl FromStr for Attenuation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Attenuation::None),
+            "linear" => Ok(Attenuation::Linear),
+            "inverse_square" => Ok(Attenuation::InverseSquare),
+            _ => Err(anyhow!("Unknown attenuation: {}", s)),
+        }
+    }
+}
+
+pub struct LightSample {
+    /// Normalized direction from the lit point towards the light
+    pub direction: Vector3D,
+    /// Distance to the light, infinite for directional lights
+    pub distance: f64,
+    /// Light arriving at the point
+    pub radiance: Vector3D,
+    /// Density of the direction over solid angle, 1 for delta lights
+    pub pdf: f64,
+    /// The light shines from a single point or in a single direction, so rays never hit it.
+    pub is_delta: bool,
+}
+
+pub trait Light: Send + Sync {
+    /// Light arriving at `point`, `None` if the light doesn't shine there.
+    fn sample(&self, point: &Vector3D, rng: &mut ThreadRng) -> Option<LightSample>;
+
+    /// Density of `sample` at `point` returning the direction towards `light_point`,
+    /// a point on the light.
+    fn pdf(&self, point: &Vector3D, light_point: &Vector3D) -> f64;
 
-pub struct Light {
-    pub position: Vector3D,
-    pub intensity: Vector3D,
+    /// Where the light is and where it shines, `None` for infinitely far lights.
+    fn bounds(&self) -> Option<LightBounds>;
 }
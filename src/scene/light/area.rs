@@ -0,0 +1,117 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::luminance;
+use crate::scene::light::bounds::{DirectionCone, LightBounds};
+use crate::scene::light::{Light, LightSample};
+
+/// Emissive triangle, glowing with the material emission from both sides
+/// or only from the front.
+pub struct AreaLight {
+    points: [Vector3D; 3],
+    normal: Vector3D,
+    area: f64,
+    radiance: Vector3D,
+    two_sided: bool,
+}
+
+impl AreaLight {
+    /// None for triangles without area, which have no normal and can't be hit.
+    pub fn new(points: [&Vector3D; 3], radiance: Vector3D, two_sided: bool) -> Option<Self> {
+        let cross = (points[1] - points[0]).cross(points[2] - points[0]);
+        let area = cross.len() / 2.0;
+        if area == 0.0 || !area.is_finite() {
+            return None;
+        }
+        Some(Self {
+            points: points.map(Vector3D::clone),
+            area,
+            normal: cross.normalize(),
+            radiance,
+            two_sided,
+        })
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, point: &Vector3D, rng: &mut ThreadRng) -> Option<LightSample> {
+        // Uniform point on the triangle
+        let root = rng.gen::<f64>().sqrt();
+        let first_weight = 1.0 - root;
+        let second_weight = rng.gen::<f64>() * root;
+        let light_point = &self.points[0] * first_weight
+            + &self.points[1] * second_weight
+            + &self.points[2] * (1.0 - first_weight - second_weight);
+
+        let pdf = self.pdf(point, &light_point);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        let to_light = &light_point - point;
+        Some(LightSample {
+            distance: to_light.len(),
+            direction: to_light.normalize(),
+            radiance: self.radiance.clone(),
+            pdf,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, point: &Vector3D, light_point: &Vector3D) -> f64 {
+        let to_light = light_point - point;
+        let distance2 = to_light.f2_norm();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+        let cos_light = &to_light.normalize() * &self.normal;
+        // The front faces points the light comes to
+        if cos_light == 0.0 || (!self.two_sided && cos_light > 0.0) {
+            return 0.0;
+        }
+        let cos_light = cos_light.abs();
+        distance2 / (self.area * cos_light)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: BoundingBox::from_point(&self.points[0])
+                .union_point(&self.points[1])
+                .union_point(&self.points[2]),
+            power: if self.two_sided { 2.0 } else { 1.0 }
+                * PI
+                * self.area
+                * luminance(&self.radiance),
+            normals: DirectionCone {
+                axis: self.normal.clone(),
+                cos_theta: 1.0,
+            },
+            cos_theta_emission: 0.0,
+            two_sided: self.two_sided,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangles_without_area_are_no_lights() {
+        let radiance = Vector3D::from([1.0, 1.0, 1.0]);
+        let a = Vector3D::from([0.0, 0.0, 0.0]);
+        let b = Vector3D::from([1.0, 0.0, 0.0]);
+        let c = Vector3D::from([2.0, 0.0, 0.0]);
+        assert!(AreaLight::new([&a, &b, &c], radiance.clone(), true).is_none());
+        assert!(AreaLight::new([&a, &a, &b], radiance.clone(), true).is_none());
+
+        let d = Vector3D::from([0.0, 1.0, 0.0]);
+        let light = AreaLight::new([&a, &b, &d], radiance, true).unwrap();
+        assert_eq!(light.area, 0.5);
+        assert!(light.normal == Vector3D::from([0.0, 0.0, 1.0]));
+    }
+}
@@ -0,0 +1,146 @@
+use std::f64::consts::PI;
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::vector::Vector3D;
+
+/// Cone of directions around `axis`.
+#[derive(Clone)]
+pub struct DirectionCone {
+    pub axis: Vector3D,
+    pub cos_theta: f64,
+}
+
+impl DirectionCone {
+    pub fn entire_sphere() -> Self {
+        Self {
+            axis: Vector3D::from([0.0, 0.0, 1.0]),
+            cos_theta: -1.0,
+        }
+    }
+
+    /// Smallest cone containing both cones.
+    pub fn union(&self, other: &DirectionCone) -> Self {
+        let theta_a = self.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_b = other.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_d = (&self.axis * &other.axis).clamp(-1.0, 1.0).acos();
+
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return self.clone();
+        }
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return other.clone();
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+        if theta_o >= PI {
+            return Self::entire_sphere();
+        }
+
+        // Rotate our axis towards the other one, so the cone just covers both
+        let rotation_axis = self.axis.cross(&other.axis);
+        if rotation_axis.f2_norm() == 0.0 {
+            return Self::entire_sphere();
+        }
+        Self {
+            axis: self
+                .axis
+                .rotate(&rotation_axis.normalize(), theta_o - theta_a),
+            cos_theta: theta_o.cos(),
+        }
+    }
+}
+
+/// `cos(max(0, a - b))` from the sines and cosines of the angles.
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// `sin(max(0, a - b))` from the sines and cosines of the angles.
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn sin_from_cos(cos: f64) -> f64 {
+    (1.0 - cos * cos).max(0.0).sqrt()
+}
+
+/// Conservative description of where a group of lights is, how bright it is and
+/// where it shines, following the light BVH of pbrt-v4 (Conty Estevez and Kulla 2018).
+#[derive(Clone)]
+pub struct LightBounds {
+    pub bounds: BoundingBox,
+    /// Total emitted power, in luminance
+    pub power: f64,
+    /// Cone containing the normals of the emitters
+    pub normals: DirectionCone,
+    /// How far from its normal an emitter shines
+    pub cos_theta_emission: f64,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn union(&self, other: &LightBounds) -> Self {
+        Self {
+            bounds: self.bounds.union(&other.bounds),
+            power: self.power + other.power,
+            normals: self.normals.union(&other.normals),
+            cos_theta_emission: self.cos_theta_emission.min(other.cos_theta_emission),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// Estimate of the light the lights may send to a surface at `point` with `normal`,
+    /// zero only if they can't light it at all. A zero normal stands for a point in a medium.
+    pub fn importance(&self, point: &Vector3D, normal: &Vector3D) -> f64 {
+        let centroid = self.bounds.centroid();
+        let distance2 = (point - &centroid)
+            .f2_norm()
+            .max(self.bounds.diagonal().len() / 2.0);
+        let to_point = (point - &centroid).normalize();
+
+        let mut cos_theta_w = &self.normals.axis * &to_point;
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+
+        // Angle the bounds subtend as seen from the point
+        let cos_theta_b = if self.bounds.contains(point) {
+            -1.0
+        } else {
+            let radius2 = (self.bounds.diagonal() / 2.0).f2_norm();
+            let sin2_theta_max = radius2 / (point - &centroid).f2_norm();
+            (1.0 - sin2_theta_max).max(0.0).sqrt()
+        };
+        let sin_theta_b = sin_from_cos(cos_theta_b);
+
+        // Smallest angle between the point and an emitter normal, minus the angle of the bounds
+        let cos_theta_o = self.normals.cos_theta;
+        let sin_theta_o = sin_from_cos(cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let cos_theta = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta <= self.cos_theta_emission {
+            return 0.0;
+        }
+
+        // Points in media have no normal and receive light from all directions
+        let cos_theta_i = if normal.f2_norm() == 0.0 {
+            1.0
+        } else {
+            let cos_theta_i = (&to_point * normal).abs();
+            let sin_theta_i = sin_from_cos(cos_theta_i);
+            cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b)
+        };
+
+        (self.power * cos_theta * cos_theta_i / distance2).max(0.0)
+    }
+}
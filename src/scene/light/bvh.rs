@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::vector::Vector3D;
+use crate::scene::light::bounds::LightBounds;
+use crate::scene::light::Light;
+
+enum LightBvhNode {
+    Leaf {
+        bounds: LightBounds,
+        light: usize,
+    },
+    Interior {
+        bounds: LightBounds,
+        children: [usize; 2],
+    },
+}
+
+impl LightBvhNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightBvhNode::Leaf { bounds, .. } | LightBvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Hierarchy over the scene lights that picks a light for a shading point
+/// with probability roughly proportional to its contribution there.
+/// Infinitely far lights have no bounds and are picked uniformly beside it.
+pub struct LightBvh {
+    nodes: Vec<LightBvhNode>,
+    parents: Vec<Option<usize>>,
+    root: Option<usize>,
+    /// Leaf node of each bounded light
+    leaves: HashMap<usize, usize>,
+    infinite_lights: Vec<usize>,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[Box<dyn Light>]) -> Self {
+        let mut bounded_lights = vec![];
+        let mut infinite_lights = vec![];
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                None => infinite_lights.push(index),
+                Some(bounds) if bounds.power > 0.0 => bounded_lights.push((index, bounds)),
+                Some(_) => {}
+            }
+        }
+
+        let mut bvh = Self {
+            nodes: vec![],
+            parents: vec![],
+            root: None,
+            leaves: HashMap::new(),
+            infinite_lights,
+        };
+        if !bounded_lights.is_empty() {
+            bvh.root = Some(bvh.build(&mut bounded_lights));
+        }
+        bvh
+    }
+
+    /// Splits the lights in halves along the longest axis of their centers, returns the node index.
+    fn build(&mut self, lights: &mut [(usize, LightBounds)]) -> usize {
+        if let [(light, bounds)] = lights {
+            self.nodes.push(LightBvhNode::Leaf {
+                bounds: bounds.clone(),
+                light: *light,
+            });
+            self.parents.push(None);
+            self.leaves.insert(*light, self.nodes.len() - 1);
+            return self.nodes.len() - 1;
+        }
+
+        let centroid_bounds = lights[1..].iter().fold(
+            BoundingBox::from_point(&lights[0].1.bounds.centroid()),
+            |centroid_bounds, (_, bounds)| centroid_bounds.union_point(&bounds.bounds.centroid()),
+        );
+        let axis = centroid_bounds.longest_axis();
+        lights.sort_by(|(_, a), (_, b)| {
+            a.bounds.centroid()[axis].total_cmp(&b.bounds.centroid()[axis])
+        });
+
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let children = [self.build(left), self.build(right)];
+        let bounds = self.nodes[children[0]]
+            .bounds()
+            .union(self.nodes[children[1]].bounds());
+        self.nodes.push(LightBvhNode::Interior { bounds, children });
+        self.parents.push(None);
+        for child in children {
+            self.parents[child] = Some(self.nodes.len() - 1);
+        }
+        self.nodes.len() - 1
+    }
+
+    fn infinite_probability(&self) -> f64 {
+        match self.root {
+            None => 1.0,
+            Some(_) => self.infinite_lights.len() as f64 / (self.infinite_lights.len() + 1) as f64,
+        }
+    }
+
+    /// Picks a light to sample at `point` on a surface with `normal`,
+    /// returns its index in the scene lights and the probability it was picked with.
+    pub fn sample(
+        &self,
+        point: &Vector3D,
+        normal: &Vector3D,
+        rng: &mut ThreadRng,
+    ) -> Option<(usize, f64)> {
+        if self.root.is_none() && self.infinite_lights.is_empty() {
+            return None;
+        }
+        let infinite_probability = self.infinite_probability();
+
+        if rng.gen::<f64>() < infinite_probability {
+            let light = self.infinite_lights[rng.gen_range(0..self.infinite_lights.len())];
+            return Some((
+                light,
+                infinite_probability / self.infinite_lights.len() as f64,
+            ));
+        }
+
+        let mut probability = 1.0 - infinite_probability;
+        let mut node = self.root?;
+        loop {
+            match &self.nodes[node] {
+                LightBvhNode::Leaf { bounds, light } => {
+                    return if bounds.importance(point, normal) > 0.0 {
+                        Some((*light, probability))
+                    } else {
+                        None
+                    };
+                }
+                LightBvhNode::Interior { children, .. } => {
+                    let importances =
+                        children.map(|child| self.nodes[child].bounds().importance(point, normal));
+                    let total = importances[0] + importances[1];
+                    if total == 0.0 {
+                        return None;
+                    }
+                    let first_probability = importances[0] / total;
+                    if rng.gen::<f64>() < first_probability {
+                        probability *= first_probability;
+                        node = children[0];
+                    } else {
+                        probability *= 1.0 - first_probability;
+                        node = children[1];
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability of `sample` at `point` with `normal` picking the light with index `light`.
+    pub fn pmf(&self, point: &Vector3D, normal: &Vector3D, light: usize) -> f64 {
+        let Some(&leaf) = self.leaves.get(&light) else {
+            return if self.infinite_lights.contains(&light) {
+                self.infinite_probability() / self.infinite_lights.len() as f64
+            } else {
+                0.0
+            };
+        };
+        if self.nodes[leaf].bounds().importance(point, normal) == 0.0 {
+            return 0.0;
+        }
+
+        let mut probability = 1.0 - self.infinite_probability();
+        let mut node = leaf;
+        while let Some(parent) = self.parents[node] {
+            if let LightBvhNode::Interior { children, .. } = &self.nodes[parent] {
+                let importances =
+                    children.map(|child| self.nodes[child].bounds().importance(point, normal));
+                let total = importances[0] + importances[1];
+                if total == 0.0 {
+                    return 0.0;
+                }
+                let side = if children[0] == node { 0 } else { 1 };
+                probability *= importances[side] / total;
+            }
+            node = parent;
+        }
+        probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::light::area::AreaLight;
+    use crate::scene::light::directional::DirectionalLight;
+    use crate::scene::light::point::PointLight;
+    use crate::scene::light::Attenuation;
+
+    fn lights() -> Vec<Box<dyn Light>> {
+        let mut lights: Vec<Box<dyn Light>> = (0..5)
+            .map(|i| {
+                Box::new(PointLight {
+                    position: Vector3D::from([i as f64 * 2.0 - 4.0, 3.0, (i % 2) as f64]),
+                    intensity: Vector3D::from([1.0 + i as f64, 1.0, 1.0]),
+                    attenuation: Attenuation::InverseSquare,
+                }) as Box<dyn Light>
+            })
+            .collect();
+        let points = [[-1.0, 5.0, -1.0], [1.0, 5.0, -1.0], [0.0, 5.0, 1.0]].map(Vector3D::from);
+        lights.push(Box::new(
+            AreaLight::new(
+                [&points[0], &points[1], &points[2]],
+                Vector3D::from([4.0, 4.0, 4.0]),
+                false,
+            )
+            .unwrap(),
+        ));
+        lights.push(Box::new(DirectionalLight {
+            direction: Vector3D::from([0.0, -1.0, 0.0]),
+            intensity: Vector3D::from([1.0, 1.0, 1.0]),
+        }));
+        lights
+    }
+
+    #[test]
+    fn pmf_matches_sampling() {
+        let lights = lights();
+        let bvh = LightBvh::new(&lights);
+        let mut rng = rand::thread_rng();
+        let normal = Vector3D::from([0.0, 1.0, 0.0]);
+        for point in [[0.0, 0.0, 0.0], [-3.0, 1.0, 2.0], [4.0, 0.5, -1.0]].map(Vector3D::from) {
+            let pmfs: Vec<f64> = (0..lights.len())
+                .map(|light| bvh.pmf(&point, &normal, light))
+                .collect();
+            assert!((pmfs.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+            const SAMPLES: usize = 100_000;
+            let mut counts = vec![0; lights.len()];
+            for _ in 0..SAMPLES {
+                let (light, probability) = bvh.sample(&point, &normal, &mut rng).unwrap();
+                assert!((probability - pmfs[light]).abs() < 1e-12);
+                counts[light] += 1;
+            }
+            for (count, pmf) in counts.iter().zip(&pmfs) {
+                let frequency = *count as f64 / SAMPLES as f64;
+                assert!((frequency - pmf).abs() < 0.01, "{} vs {}", frequency, pmf);
+            }
+        }
+    }
+
+    #[test]
+    fn one_sided_lights_are_never_picked_from_behind() {
+        let lights = lights();
+        let bvh = LightBvh::new(&lights);
+        let mut rng = rand::thread_rng();
+        let normal = Vector3D::from([0.0, 1.0, 0.0]);
+        // The area light faces down
+        let area_light = lights.len() - 2;
+        assert!(bvh.pmf(&Vector3D::default(), &normal, area_light) > 0.0);
+
+        let above = Vector3D::from([0.0, 10.0, 0.0]);
+        assert_eq!(bvh.pmf(&above, &normal, area_light), 0.0);
+        let pmfs: f64 = (0..lights.len())
+            .map(|light| bvh.pmf(&above, &normal, light))
+            .sum();
+        assert!((pmfs - 1.0).abs() < 1e-9);
+        for _ in 0..10_000 {
+            let (light, _) = bvh.sample(&above, &normal, &mut rng).unwrap();
+            assert_ne!(light, area_light);
+        }
+    }
+}
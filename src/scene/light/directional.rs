@@ -0,0 +1,32 @@
+use rand::rngs::ThreadRng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::light::bounds::LightBounds;
+use crate::scene::light::{Light, LightSample};
+
+/// Infinitely far light, like the sun, shining in one direction everywhere.
+pub struct DirectionalLight {
+    /// Direction the light travels in
+    pub direction: Vector3D,
+    pub intensity: Vector3D,
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: &Vector3D, _rng: &mut ThreadRng) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -&self.direction,
+            distance: f64::INFINITY,
+            radiance: self.intensity.clone(),
+            pdf: 1.0,
+            is_delta: true,
+        })
+    }
+
+    fn pdf(&self, _point: &Vector3D, _light_point: &Vector3D) -> f64 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
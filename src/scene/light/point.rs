@@ -0,0 +1,44 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::luminance;
+use crate::scene::light::bounds::{DirectionCone, LightBounds};
+use crate::scene::light::{Attenuation, Light, LightSample};
+
+/// Light shining equally in all directions from a point, the `P` directive.
+pub struct PointLight {
+    pub position: Vector3D,
+    pub intensity: Vector3D,
+    pub attenuation: Attenuation,
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: &Vector3D, _rng: &mut ThreadRng) -> Option<LightSample> {
+        let to_light = &self.position - point;
+        let distance = to_light.len();
+        Some(LightSample {
+            direction: to_light.normalize(),
+            distance,
+            radiance: &self.intensity * self.attenuation.factor(distance),
+            pdf: 1.0,
+            is_delta: true,
+        })
+    }
+
+    fn pdf(&self, _point: &Vector3D, _light_point: &Vector3D) -> f64 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: BoundingBox::from_point(&self.position),
+            power: 4.0 * PI * luminance(&self.intensity),
+            normals: DirectionCone::entire_sphere(),
+            cos_theta_emission: 0.0,
+            two_sided: false,
+        })
+    }
+}
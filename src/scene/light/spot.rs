@@ -0,0 +1,131 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::Rgb32FImage;
+use rand::rngs::ThreadRng;
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::frame::Frame;
+use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::luminance;
+use crate::scene::light::bounds::{DirectionCone, LightBounds};
+use crate::scene::light::{Attenuation, Light, LightSample};
+
+/// Point light limited to a cone, optionally projecting an image (gobo) like a slide projector.
+pub struct SpotLight {
+    position: Vector3D,
+    frame: Frame,
+    intensity: Vector3D,
+    attenuation: Attenuation,
+    cos_cone: f64,
+    cos_falloff: f64,
+    tan_cone: f64,
+    gobo: Option<Rgb32FImage>,
+}
+
+impl SpotLight {
+    /// `cone` is the half angle of the lit cone and `falloff` is the half angle
+    /// where the light starts to fade out towards it, in degrees.
+    pub fn new(
+        position: Vector3D,
+        direction: Vector3D,
+        intensity: Vector3D,
+        cone: f64,
+        falloff: f64,
+        attenuation: Attenuation,
+        gobo_path: Option<&Path>,
+    ) -> Result<Self> {
+        let gobo = match gobo_path {
+            None => None,
+            Some(path) => match image::open(path) {
+                Ok(image) => Some(image.into_rgb32f()),
+                Err(err) => return Err(anyhow!("Couldn't open gobo {}: {}", path.display(), err)),
+            },
+        };
+        Ok(Self {
+            position,
+            frame: Frame::from_normal(&direction.normalize()),
+            intensity,
+            attenuation,
+            cos_cone: cone.to_radians().cos(),
+            cos_falloff: falloff.min(cone).to_radians().cos(),
+            tan_cone: cone.to_radians().tan(),
+            gobo,
+        })
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff {
+            return 1.0;
+        }
+        let t = ((cos_theta - self.cos_cone) / (self.cos_falloff - self.cos_cone)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Color of the projected image, which covers the square around the cone.
+    fn projection(&self, local_direction: &Vector3D) -> Vector3D {
+        match self.gobo {
+            None => Vector3D::from([1.0, 1.0, 1.0]),
+            Some(ref gobo) => {
+                let scale = local_direction.z * self.tan_cone;
+                let u = 0.5 + 0.5 * local_direction.x / scale;
+                let v = 0.5 - 0.5 * local_direction.y / scale;
+                let x = ((u * gobo.width() as f64) as u32).min(gobo.width() - 1);
+                let y = ((v * gobo.height() as f64) as u32).min(gobo.height() - 1);
+                let color = gobo.get_pixel(x, y).0;
+                Vector3D {
+                    x: color[0] as f64,
+                    y: color[1] as f64,
+                    z: color[2] as f64,
+                }
+            }
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: &Vector3D, _rng: &mut ThreadRng) -> Option<LightSample> {
+        let to_light = &self.position - point;
+        let distance = to_light.len();
+        let direction = to_light.normalize();
+
+        let local_direction = self.frame.to_local(&-&direction);
+        if local_direction.z < self.cos_cone {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self
+                .projection(&local_direction)
+                .component_mul(&self.intensity)
+                * (self.falloff(local_direction.z) * self.attenuation.factor(distance)),
+            pdf: 1.0,
+            is_delta: true,
+        })
+    }
+
+    fn pdf(&self, _point: &Vector3D, _light_point: &Vector3D) -> f64 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Full intensity inside the falloff cone and about half of it in the fading ring
+        let power = 2.0
+            * PI
+            * luminance(&self.intensity)
+            * ((1.0 - self.cos_falloff) + (self.cos_falloff - self.cos_cone) / 2.0);
+        Some(LightBounds {
+            bounds: BoundingBox::from_point(&self.position),
+            power,
+            normals: DirectionCone {
+                axis: self.frame.normal.clone(),
+                cos_theta: self.cos_falloff,
+            },
+            cos_theta_emission: (self.cos_cone.acos() - self.cos_falloff.acos()).cos(),
+            two_sided: false,
+        })
+    }
+}
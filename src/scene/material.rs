@@ -1,21 +1,70 @@
+use std::sync::Arc;
+
 use crate::geometry::vector::Vector3D;
+use crate::scene::bsdf::dielectric::{Dielectric, Dispersion};
+use crate::scene::bsdf::emissive::Emissive;
+use crate::scene::bsdf::glossy::Glossy;
+use crate::scene::bsdf::lambertian::Lambertian;
+use crate::scene::bsdf::mirror::Mirror;
+use crate::scene::bsdf::mixture::Mixture;
+use crate::scene::bsdf::{luminance, Bsdf};
+use crate::scene::medium::Medium;
+use crate::scene::microfacet::Ggx;
+use crate::scene::texture::{ImageTexture, SurfacePoint, Texture};
+
+/// Material parameters textures can drive, by their `.mtl` keys.
+#[derive(Clone, Copy)]
+pub enum TexturedParameter {
+    /// `Ka`
+    Ambient,
+    /// `Kd`
+    Diffuse,
+    /// `Ks`
+    Specular,
+    /// `Ns`
+    SpecularExponent,
+    /// `Pr`
+    Roughness,
+    /// `Pm`
+    Metallic,
+    /// `d`, the chance of a ray hitting the surface
+    Dissolve,
+}
 
 #[derive(Clone)]
 pub struct Material {
     pub name: String,
+    pub id: usize,
     pub ambient_color: Vector3D,
     pub diffuse_color: Vector3D,
     pub specular_color: Vector3D,
     pub intensity: Vector3D,
-    pub specular_exponent: f64,
+    pub specular_exponent: Option<f64>,
+    pub roughness: Option<f64>,
+    pub metallic: f64,
     pub refraction_index: f64,
+    pub dispersion: Option<Dispersion>,
     pub albedo: Vector3D,
+    pub illum: Option<usize>,
+    pub dissolve: f64,
+    /// Surfaces seen only from the front, rays pass through their backs
+    pub one_sided: bool,
+    /// Medium inside of closed meshes with this material
+    pub medium: Option<Medium>,
+    /// Tangent space normal map
+    pub normal_map: Option<Arc<ImageTexture>>,
+    /// Height map and the height of its white texels in scene units
+    pub bump_map: Option<(Arc<ImageTexture>, f64)>,
+    /// Parameters replaced by textures, scalar ones take the average of the channels
+    pub textures: Vec<(TexturedParameter, Arc<Texture>)>,
+    pub bsdf: Arc<dyn Bsdf>,
 }
 
 impl Default for Material {
     fn default() -> Self {
         Self {
             name: "".to_owned(),
+            id: 0,
             ambient_color: Vector3D {
                 x: 0.0,
                 y: 0.0,
@@ -36,13 +85,261 @@ impl Default for Material {
                 y: 0.0,
                 z: 0.0,
             },
-            specular_exponent: 0.0,
+            specular_exponent: None,
+            roughness: None,
+            metallic: 0.0,
             refraction_index: 1.0,
+            dispersion: None,
             albedo: Vector3D {
                 x: 1.0,
                 y: 0.0,
                 z: 0.0,
             },
+            illum: None,
+            dissolve: 1.0,
+            one_sided: false,
+            medium: None,
+            normal_map: None,
+            bump_map: None,
+            textures: vec![],
+            bsdf: Arc::new(Emissive),
+        }
+    }
+}
+
+impl Material {
+    /// Fully dissolved (`d 0`) surfaces are invisible, they only bound the medium inside.
+    pub fn is_interface(&self) -> bool {
+        self.dissolve == 0.0
+    }
+
+    /// Partly dissolved surfaces, by a fractional `d` or a `map_d` texture, let rays through.
+    pub fn is_cutout(&self) -> bool {
+        self.dissolve > 0.0
+            && (self.dissolve < 1.0
+                || self
+                    .textures
+                    .iter()
+                    .any(|(parameter, _)| matches!(parameter, TexturedParameter::Dissolve)))
+    }
+
+    /// Chance that a ray hits the surface at a point rather than passing through.
+    pub fn opacity(&self, surface: &SurfacePoint) -> f64 {
+        self.textures
+            .iter()
+            .filter(|(parameter, _)| matches!(parameter, TexturedParameter::Dissolve))
+            .fold(self.dissolve, |opacity, (_, texture)| {
+                let color = texture.evaluate(surface);
+                opacity * (color.x + color.y + color.z) / 3.0
+            })
+    }
+
+    /// Emissive surfaces are sampled as area lights.
+    pub fn is_emissive(&self) -> bool {
+        luminance(&self.intensity) > 0.0
+    }
+
+    /// Scattering at a surface point, with the textured parameters evaluated there.
+    pub fn at_surface(&self, surface: &SurfacePoint) -> SurfaceMaterial<'_> {
+        let mut parameters = None;
+        for (parameter, texture) in self.textures.iter() {
+            if matches!(parameter, TexturedParameter::Dissolve) {
+                // Only decides whether the surface is hit, see `opacity`
+                continue;
+            }
+            let parameters = parameters.get_or_insert_with(|| self.parameters());
+            let color = texture.evaluate(surface);
+            let value = (color.x + color.y + color.z) / 3.0;
+            match parameter {
+                TexturedParameter::Ambient => parameters.ambient_color = color,
+                TexturedParameter::Diffuse => parameters.diffuse_color = color,
+                TexturedParameter::Specular => parameters.specular_color = color,
+                TexturedParameter::SpecularExponent => parameters.specular_exponent = Some(value),
+                TexturedParameter::Roughness => parameters.roughness = Some(value),
+                TexturedParameter::Metallic => parameters.metallic = value,
+                TexturedParameter::Dissolve => {}
+            }
+        }
+
+        SurfaceMaterial {
+            material: self,
+            textured: parameters.map(|parameters| Textured {
+                bsdf: self.bsdf_with(&parameters),
+                ambient_color: parameters.ambient_color,
+            }),
+        }
+    }
+
+    fn parameters(&self) -> SurfaceParameters {
+        SurfaceParameters {
+            ambient_color: self.ambient_color.clone(),
+            diffuse_color: self.diffuse_color.clone(),
+            specular_color: self.specular_color.clone(),
+            specular_exponent: self.specular_exponent,
+            roughness: self.roughness,
+            metallic: self.metallic,
+        }
+    }
+
+    fn dielectric_bsdf(&self) -> Arc<dyn Bsdf> {
+        Arc::new(Dielectric {
+            refraction_index: self.refraction_index,
+            dispersion: self.dispersion.clone(),
+        })
+    }
+
+    /// Picks the scattering model by the `illum` key.
+    /// Without it the `al` weights mix glossy, reflective and refractive behaviour.
+    pub fn build_bsdf(&self) -> Arc<dyn Bsdf> {
+        self.bsdf_with(&self.parameters())
+    }
+
+    fn bsdf_with(&self, parameters: &SurfaceParameters) -> Arc<dyn Bsdf> {
+        match self.illum {
+            Some(0) => Arc::new(Emissive),
+            Some(1) => Arc::new(Lambertian {
+                color: parameters.diffuse_color.clone(),
+            }),
+            Some(3 | 5 | 8) => parameters.reflection_bsdf(),
+            Some(4 | 6 | 7 | 9) => self.dielectric_bsdf(),
+            Some(_) => parameters.glossy_bsdf(),
+            None => {
+                let components: Vec<(f64, Arc<dyn Bsdf>)> = [
+                    (self.albedo.x, parameters.glossy_bsdf()),
+                    (self.albedo.y, parameters.reflection_bsdf()),
+                    (self.albedo.z, self.dielectric_bsdf()),
+                ]
+                .into_iter()
+                .filter(|(weight, _)| *weight != 0.0)
+                .collect();
+
+                if components.is_empty() {
+                    Arc::new(Emissive)
+                } else {
+                    Arc::new(Mixture { components })
+                }
+            }
+        }
+    }
+}
+
+/// Parameters of a material that textures can change.
+struct SurfaceParameters {
+    ambient_color: Vector3D,
+    diffuse_color: Vector3D,
+    specular_color: Vector3D,
+    specular_exponent: Option<f64>,
+    roughness: Option<f64>,
+    metallic: f64,
+}
+
+impl SurfaceParameters {
+    /// `Pr` if given, otherwise derived from the Phong exponent `Ns` if that is given.
+    fn roughness(&self) -> Option<f64> {
+        self.roughness.or_else(|| {
+            self.specular_exponent
+                .map(|exponent| (2.0 / (exponent + 2.0)).sqrt())
+        })
+    }
+
+    fn specular_f0(&self) -> Vector3D {
+        &self.specular_color * (1.0 - self.metallic) + &self.diffuse_color * self.metallic
+    }
+
+    /// Tint of mirror and glossy reflections: none for dielectrics, base color for metals.
+    fn reflection_tint(&self) -> Vector3D {
+        Vector3D::from([1.0 - self.metallic; 3]) + &self.diffuse_color * self.metallic
+    }
+
+    fn glossy_bsdf(&self) -> Arc<dyn Bsdf> {
+        Arc::new(Glossy {
+            diffuse: &self.diffuse_color * (1.0 - self.metallic),
+            f0: self.specular_f0(),
+            microfacets: Ggx::new(self.roughness().unwrap_or(1.0)),
+        })
+    }
+
+    /// Perfect mirror unless a roughness is given.
+    fn reflection_bsdf(&self) -> Arc<dyn Bsdf> {
+        let microfacets = Ggx::new(self.roughness().unwrap_or(0.0));
+        if microfacets.is_smooth() {
+            Arc::new(Mirror {
+                f0: self.reflection_tint(),
+            })
+        } else {
+            Arc::new(Glossy {
+                diffuse: Vector3D::default(),
+                f0: self.reflection_tint(),
+                microfacets,
+            })
+        }
+    }
+}
+
+struct Textured {
+    ambient_color: Vector3D,
+    bsdf: Arc<dyn Bsdf>,
+}
+
+/// Material at a surface point. Only materials with textures get parameters of their own there.
+pub struct SurfaceMaterial<'a> {
+    material: &'a Material,
+    textured: Option<Textured>,
+}
+
+impl SurfaceMaterial<'_> {
+    pub fn ambient_color(&self) -> &Vector3D {
+        match self.textured {
+            Some(ref textured) => &textured.ambient_color,
+            None => &self.material.ambient_color,
+        }
+    }
+
+    pub fn bsdf(&self) -> &dyn Bsdf {
+        match self.textured {
+            Some(ref textured) => textured.bsdf.as_ref(),
+            None => self.material.bsdf.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::thread_rng;
+
+    use super::*;
+
+    /// Whether the material reflects light only into the mirror direction.
+    fn is_mirror(material: &Material) -> bool {
+        let outgoing = Vector3D::from([0.6, 0.0, 0.8]);
+        let sample = material
+            .build_bsdf()
+            .sample(&outgoing, false, None, &mut thread_rng());
+        sample.is_some_and(|sample| {
+            sample.is_delta && sample.incoming == Vector3D::from([-0.6, 0.0, 0.8])
+        })
+    }
+
+    #[test]
+    fn reflective_materials_without_roughness_are_mirrors() {
+        let reflective = Material {
+            illum: Some(3),
+            ..Material::default()
+        };
+        let reflection_weighted = Material {
+            albedo: Vector3D::from([0.0, 1.0, 0.0]),
+            ..Material::default()
+        };
+        for material in [reflective, reflection_weighted] {
+            assert!(is_mirror(&material));
+            assert!(!is_mirror(&Material {
+                specular_exponent: Some(10.0),
+                ..material.clone()
+            }));
+            assert!(!is_mirror(&Material {
+                roughness: Some(0.5),
+                ..material
+            }));
         }
     }
 }
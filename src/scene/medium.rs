@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::frame::Frame;
+use crate::geometry::ray::Ray;
+use crate::geometry::vector::Vector3D;
+use grid::GridMedium;
+use homogeneous::HomogeneousMedium;
+
+pub mod grid;
+pub mod homogeneous;
+
+/// Henyey-Greenstein phase function. Directions point away from the scattering point,
+/// like the BSDF ones, and positive asymmetry scatters forward.
+#[derive(Clone, Default)]
+pub struct HenyeyGreenstein {
+    pub asymmetry: f64,
+}
+
+impl HenyeyGreenstein {
+    /// Value of the phase function, which is also the density of `sample`.
+    pub fn evaluate(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64 {
+        let g = self.asymmetry;
+        let cos_theta = -(outgoing * incoming);
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+    }
+
+    pub fn sample(&self, outgoing: &Vector3D, rng: &mut ThreadRng) -> Vector3D {
+        let g = self.asymmetry;
+        let u = rng.gen::<f64>();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - term * term) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+
+        Frame::from_normal(&-outgoing).to_world(&Vector3D {
+            x: sin_theta * phi.cos(),
+            y: sin_theta * phi.sin(),
+            z: cos_theta,
+        })
+    }
+}
+
+/// What happens to a ray travelling through a medium.
+pub enum MediumInteraction {
+    /// The ray scatters at `distance` along it
+    Scatter { distance: f64, weight: Vector3D },
+    /// The ray is absorbed, only the light emitted by the medium there reaches its origin
+    Absorb { emission: Vector3D },
+    /// The ray reaches the end of its segment
+    Pass { weight: Vector3D },
+}
+
+fn average(color: &Vector3D) -> f64 {
+    (color.x + color.y + color.z) / 3.0
+}
+
+fn max_component(color: &Vector3D) -> f64 {
+    color.x.max(color.y).max(color.z)
+}
+
+#[derive(Clone)]
+pub enum Medium {
+    Homogeneous(HomogeneousMedium),
+    Grid(GridMedium),
+}
+
+impl Medium {
+    /// Absorption, scattering and phase function, per unit of density for grids.
+    pub fn coefficients_mut(&mut self) -> &mut HomogeneousMedium {
+        match self {
+            Medium::Homogeneous(medium) => medium,
+            Medium::Grid(medium) => &mut medium.coefficients,
+        }
+    }
+
+    pub fn phase(&self) -> &HenyeyGreenstein {
+        match self {
+            Medium::Homogeneous(medium) => &medium.phase,
+            Medium::Grid(medium) => &medium.coefficients.phase,
+        }
+    }
+
+    /// Samples a free flight distance of `ray` in the medium over the segment of `max_distance`.
+    pub fn sample_interaction(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        rng: &mut ThreadRng,
+    ) -> MediumInteraction {
+        match self {
+            Medium::Homogeneous(medium) => medium.sample_interaction(max_distance, rng),
+            Medium::Grid(medium) => medium.sample_interaction(ray, max_distance, rng),
+        }
+    }
+
+    /// Fraction of light left after `distance` along `ray`, which may be infinite.
+    /// Estimated without bias for grids.
+    pub fn transmittance(&self, ray: &Ray, distance: f64, rng: &mut ThreadRng) -> Vector3D {
+        match self {
+            Medium::Homogeneous(medium) => medium.transmittance(distance),
+            Medium::Grid(medium) => medium.transmittance(ray, distance, rng),
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::ray::Ray;
+use crate::geometry::vector::Vector3D;
+use crate::scene::medium::homogeneous::HomogeneousMedium;
+use crate::scene::medium::{average, max_component, MediumInteraction};
+use crate::scene::spectrum::blackbody;
+
+static GRID_MAGIC: &[u8; 4] = b"RTVG";
+static GRID_VERSION: u32 = 1;
+
+/// Resolution of the precomputed blackbody colors.
+static EMISSION_TABLE_SIZE: usize = 256;
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// Density and optionally temperature sampled on a regular grid of voxel centers.
+///
+/// Grid files are little endian binary:
+/// - magic `RTVG` and `u32` version 1
+/// - `u32` resolution along x, y and z
+/// - `f32` world space bounds, min x, y, z then max x, y, z
+/// - `u32` number of channels, 1 for density or 2 for density and temperature in kelvins
+/// - `f32` values of each channel in turn, x changing fastest, then y, then z
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    bounds: BoundingBox,
+    density: Vec<f32>,
+    temperature: Option<Vec<f32>>,
+    max_density: f64,
+    max_temperature: f64,
+}
+
+impl VoxelGrid {
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != GRID_MAGIC {
+            return Err(anyhow!("Not a voxel grid file"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != GRID_VERSION {
+            return Err(anyhow!("Unsupported voxel grid version {}", version));
+        }
+
+        let mut resolution = [0; 3];
+        for size in resolution.iter_mut() {
+            *size = read_u32(&mut reader)? as usize;
+        }
+        if resolution.contains(&0) {
+            return Err(anyhow!("Voxel grid is empty"));
+        }
+        let mut corners = [0.0; 6];
+        for corner in corners.iter_mut() {
+            *corner = read_f32(&mut reader)? as f64;
+        }
+        let bounds = BoundingBox {
+            min: Vector3D::from([corners[0], corners[1], corners[2]]),
+            max: Vector3D::from([corners[3], corners[4], corners[5]]),
+        };
+
+        let channels = read_u32(&mut reader)?;
+        if channels != 1 && channels != 2 {
+            return Err(anyhow!(
+                "Voxel grid must have 1 or 2 channels, got {}",
+                channels
+            ));
+        }
+        let voxels = resolution.iter().product();
+        let mut read_channel =
+            || -> Result<Vec<f32>> { (0..voxels).map(|_| read_f32(&mut reader)).collect() };
+        let density = read_channel()?;
+        let temperature = if channels == 2 {
+            Some(read_channel()?)
+        } else {
+            None
+        };
+
+        let max_of = |values: &[f32]| {
+            values
+                .iter()
+                .fold(0.0, |max: f64, &value| max.max(value as f64))
+        };
+        Ok(Self {
+            resolution,
+            bounds,
+            max_density: max_of(&density),
+            max_temperature: temperature.as_deref().map_or(0.0, max_of),
+            density,
+            temperature,
+        })
+    }
+
+    /// Trilinear interpolation between voxel centers, zero outside of the grid.
+    fn lookup(&self, values: &[f32], point: &Vector3D) -> f64 {
+        if !self.bounds.contains(point) {
+            return 0.0;
+        }
+
+        let diagonal = self.bounds.diagonal();
+        let mut cells = [(0, 0, 0.0); 3];
+        for (axis, cell) in cells.iter_mut().enumerate() {
+            let size = self.resolution[axis];
+            let position =
+                (point[axis] - self.bounds.min[axis]) / diagonal[axis] * size as f64 - 0.5;
+            let position = position.clamp(0.0, (size - 1) as f64);
+            let lower = (position as usize).min(size - 1);
+            *cell = (lower, (lower + 1).min(size - 1), position - lower as f64);
+        }
+
+        let [(x0, x1, dx), (y0, y1, dy), (z0, z1, dz)] = cells;
+        let value = |x: usize, y: usize, z: usize| {
+            values[x + self.resolution[0] * (y + self.resolution[1] * z)] as f64
+        };
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        lerp(
+            lerp(
+                lerp(value(x0, y0, z0), value(x1, y0, z0), dx),
+                lerp(value(x0, y1, z0), value(x1, y1, z0), dx),
+                dy,
+            ),
+            lerp(
+                lerp(value(x0, y0, z1), value(x1, y0, z1), dx),
+                lerp(value(x0, y1, z1), value(x1, y1, z1), dx),
+                dy,
+            ),
+            dz,
+        )
+    }
+
+    pub fn density(&self, point: &Vector3D) -> f64 {
+        self.lookup(&self.density, point)
+    }
+
+    pub fn temperature(&self, point: &Vector3D) -> f64 {
+        match self.temperature {
+            None => 0.0,
+            Some(ref temperature) => self.lookup(temperature, point),
+        }
+    }
+}
+
+/// Heterogeneous medium with density from a voxel grid, like simulated smoke.
+/// Hot voxels glow as blackbodies, scaled by `emission_scale`.
+#[derive(Clone)]
+pub struct GridMedium {
+    /// Absorption, scattering and phase function at unit density
+    pub coefficients: HomogeneousMedium,
+    pub emission_scale: f64,
+    grid: Arc<VoxelGrid>,
+    /// Blackbody colors from zero to the maximum temperature of the grid
+    emission_table: Arc<Vec<Vector3D>>,
+}
+
+impl GridMedium {
+    pub fn new(coefficients: HomogeneousMedium, grid: VoxelGrid) -> Self {
+        let emission_table = (0..EMISSION_TABLE_SIZE)
+            .map(|i| blackbody(grid.max_temperature * i as f64 / (EMISSION_TABLE_SIZE - 1) as f64))
+            .collect();
+        Self {
+            coefficients,
+            emission_scale: 1.0,
+            grid: Arc::new(grid),
+            emission_table: Arc::new(emission_table),
+        }
+    }
+
+    fn emission(&self, point: &Vector3D) -> Vector3D {
+        if self.grid.max_temperature <= 0.0 || self.emission_scale == 0.0 {
+            return Vector3D::default();
+        }
+        let position = self.grid.temperature(point) / self.grid.max_temperature
+            * (EMISSION_TABLE_SIZE - 1) as f64;
+        let lower = (position.max(0.0) as usize).min(EMISSION_TABLE_SIZE - 2);
+        let t = (position - lower as f64).clamp(0.0, 1.0);
+        (&self.emission_table[lower] * (1.0 - t) + &self.emission_table[lower + 1] * t)
+            * self.emission_scale
+    }
+
+    /// Extinction bounding the one anywhere in the grid, which turns it into a homogeneous
+    /// medium of real and fictitious null particles.
+    fn majorant(&self) -> f64 {
+        max_component(&self.coefficients.extinction()) * self.grid.max_density
+    }
+
+    /// Part of `ray` up to `max_distance` inside the grid.
+    fn clip(&self, ray: &Ray, max_distance: f64) -> Option<(f64, f64)> {
+        let (near, far) = self.grid.bounds.intersect(ray)?;
+        let near = near.max(0.0);
+        let far = far.min(max_distance);
+        if near < far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+
+    /// Delta tracking (Woodcock et al. 1965) with the absorption, scattering and null
+    /// collision probabilities averaged over the color channels and the throughput
+    /// weighted accordingly. Absorption is only sampled explicitly where the medium glows.
+    pub fn sample_interaction(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        rng: &mut ThreadRng,
+    ) -> MediumInteraction {
+        let mut throughput = Vector3D::from([1.0, 1.0, 1.0]);
+        let majorant = self.majorant();
+        let Some((near, far)) = self.clip(ray, max_distance).filter(|_| majorant > 0.0) else {
+            return MediumInteraction::Pass { weight: throughput };
+        };
+
+        let mut distance = near;
+        loop {
+            distance -= (1.0 - rng.gen::<f64>()).ln() / majorant;
+            if distance >= far {
+                return MediumInteraction::Pass { weight: throughput };
+            }
+
+            let point = &ray.from + &ray.direction * distance;
+            let density = self.grid.density(&point);
+            let absorption = &self.coefficients.absorption * density;
+            let scattering = &self.coefficients.scattering * density;
+            let emission = self.emission(&point);
+
+            let absorb_probability = if max_component(&emission) > 0.0 {
+                average(&absorption) / majorant
+            } else {
+                0.0
+            };
+            let scatter_probability = average(&scattering) / majorant;
+            let null_probability = 1.0 - absorb_probability - scatter_probability;
+
+            let u = rng.gen::<f64>();
+            if u < absorb_probability {
+                return MediumInteraction::Absorb {
+                    emission: throughput
+                        .component_mul(&absorption)
+                        .component_mul(&emission)
+                        / (majorant * absorb_probability),
+                };
+            }
+            if u < absorb_probability + scatter_probability {
+                return MediumInteraction::Scatter {
+                    distance,
+                    weight: throughput.component_mul(&scattering)
+                        / (majorant * scatter_probability),
+                };
+            }
+
+            if null_probability <= 0.0 {
+                return MediumInteraction::Pass {
+                    weight: Vector3D::default(),
+                };
+            }
+            let null = Vector3D::from([majorant, majorant, majorant]) - &absorption - &scattering;
+            throughput = throughput.component_mul(&null) / (majorant * null_probability);
+        }
+    }
+
+    /// Ratio tracking (Novák et al. 2014).
+    pub fn transmittance(&self, ray: &Ray, distance: f64, rng: &mut ThreadRng) -> Vector3D {
+        let mut transmittance = Vector3D::from([1.0, 1.0, 1.0]);
+        let majorant = self.majorant();
+        let Some((near, far)) = self.clip(ray, distance).filter(|_| majorant > 0.0) else {
+            return transmittance;
+        };
+
+        let extinction = self.coefficients.extinction();
+        let mut distance = near;
+        loop {
+            distance -= (1.0 - rng.gen::<f64>()).ln() / majorant;
+            if distance >= far {
+                return transmittance;
+            }
+
+            let density = self.grid.density(&(&ray.from + &ray.direction * distance));
+            let null = Vector3D::from([1.0, 1.0, 1.0]) - &extinction * (density / majorant);
+            transmittance = transmittance.component_mul(&null);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_file(resolution: [u32; 3], bounds: [f32; 6], channels: &[Vec<f32>]) -> Vec<u8> {
+        let mut bytes = GRID_MAGIC.to_vec();
+        bytes.extend(GRID_VERSION.to_le_bytes());
+        resolution
+            .iter()
+            .for_each(|size| bytes.extend(size.to_le_bytes()));
+        bounds
+            .iter()
+            .for_each(|corner| bytes.extend(corner.to_le_bytes()));
+        bytes.extend((channels.len() as u32).to_le_bytes());
+        channels
+            .iter()
+            .flatten()
+            .for_each(|value| bytes.extend(value.to_le_bytes()));
+        bytes
+    }
+
+    fn read_bytes(name: &str, bytes: &[u8]) -> Result<VoxelGrid> {
+        let path = std::env::temp_dir().join(format!("grid_{}_{}.rtvg", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let grid = VoxelGrid::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        grid
+    }
+
+    #[test]
+    fn reads_voxels_with_x_changing_fastest() {
+        // 2x2x2 voxels over [0, 2]³, their centers at 0.5 and 1.5
+        let density: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let temperature = vec![1000.0; 8];
+        let bytes = grid_file(
+            [2, 2, 2],
+            [0.0, 0.0, 0.0, 2.0, 2.0, 2.0],
+            &[density, temperature],
+        );
+        let grid = read_bytes("order", &bytes).unwrap();
+
+        assert_eq!(grid.max_density, 7.0);
+        assert_eq!(grid.max_temperature, 1000.0);
+        assert_eq!(grid.density(&Vector3D::from([0.5, 0.5, 0.5])), 0.0);
+        assert_eq!(grid.density(&Vector3D::from([1.5, 0.5, 0.5])), 1.0);
+        assert_eq!(grid.density(&Vector3D::from([0.5, 1.5, 0.5])), 2.0);
+        assert_eq!(grid.density(&Vector3D::from([0.5, 0.5, 1.5])), 4.0);
+        assert_eq!(grid.density(&Vector3D::from([1.0, 1.0, 1.0])), 3.5);
+        // Clamped to the outer voxel centers inside the bounds, zero outside
+        assert_eq!(grid.density(&Vector3D::from([2.0, 2.0, 2.0])), 7.0);
+        assert_eq!(grid.density(&Vector3D::from([2.5, 1.0, 1.0])), 0.0);
+        assert_eq!(grid.temperature(&Vector3D::from([1.2, 0.3, 1.9])), 1000.0);
+    }
+
+    #[test]
+    fn density_only_grids_have_no_temperature() {
+        let bytes = grid_file([1, 1, 1], [0.0, 0.0, 0.0, 1.0, 1.0, 1.0], &[vec![0.5]]);
+        let grid = read_bytes("density", &bytes).unwrap();
+        assert_eq!(grid.density(&Vector3D::from([0.2, 0.7, 0.9])), 0.5);
+        assert_eq!(grid.temperature(&Vector3D::from([0.5, 0.5, 0.5])), 0.0);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let bounds = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let valid = grid_file([2, 1, 1], bounds, &[vec![1.0, 2.0]]);
+
+        let mut wrong_magic = valid.clone();
+        wrong_magic[0] = b'X';
+        assert!(read_bytes("magic", &wrong_magic).is_err());
+        let mut wrong_version = valid.clone();
+        wrong_version[4] = 2;
+        assert!(read_bytes("version", &wrong_version).is_err());
+        assert!(read_bytes("truncated", &valid[..valid.len() - 1]).is_err());
+        assert!(read_bytes("empty", &grid_file([0, 1, 1], bounds, &[vec![]])).is_err());
+        let three_channels = grid_file([1, 1, 1], bounds, &[vec![1.0], vec![1.0], vec![1.0]]);
+        assert!(read_bytes("channels", &three_channels).is_err());
+        let missing =
+            std::env::temp_dir().join(format!("grid_{}_missing.rtvg", std::process::id()));
+        assert!(VoxelGrid::read(&missing).is_err());
+    }
+}
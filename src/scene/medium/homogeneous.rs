@@ -0,0 +1,80 @@
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::medium::{average, HenyeyGreenstein, MediumInteraction};
+
+/// Participating medium with the same absorption and scattering everywhere, like fog or murky water.
+/// Coefficients are per scene unit of length.
+#[derive(Clone, Default)]
+pub struct HomogeneousMedium {
+    pub absorption: Vector3D,
+    pub scattering: Vector3D,
+    pub phase: HenyeyGreenstein,
+}
+
+fn channel_transmittance(extinction: f64, distance: f64) -> f64 {
+    if extinction == 0.0 {
+        1.0
+    } else {
+        (-extinction * distance).exp()
+    }
+}
+
+impl HomogeneousMedium {
+    pub fn extinction(&self) -> Vector3D {
+        &self.absorption + &self.scattering
+    }
+
+    /// Fraction of light left after `distance`, which may be infinite.
+    pub fn transmittance(&self, distance: f64) -> Vector3D {
+        let extinction = self.extinction();
+        Vector3D {
+            x: channel_transmittance(extinction.x, distance),
+            y: channel_transmittance(extinction.y, distance),
+            z: channel_transmittance(extinction.z, distance),
+        }
+    }
+
+    /// Samples a free flight distance for a ray segment of `max_distance`,
+    /// picking a color channel uniformly and averaging the densities over the channels.
+    pub fn sample_interaction(&self, max_distance: f64, rng: &mut ThreadRng) -> MediumInteraction {
+        // Purely absorbing media, like colored glass, only attenuate
+        if self.scattering == Vector3D::default() {
+            return MediumInteraction::Pass {
+                weight: self.transmittance(max_distance),
+            };
+        }
+
+        let extinction = self.extinction();
+        let channel_extinction = match rng.gen_range(0..3) {
+            0 => extinction.x,
+            1 => extinction.y,
+            _ => extinction.z,
+        };
+        let distance = if channel_extinction == 0.0 {
+            f64::INFINITY
+        } else {
+            -(1.0 - rng.gen::<f64>()).ln() / channel_extinction
+        };
+
+        if distance < max_distance {
+            let transmittance = self.transmittance(distance);
+            let pdf = average(&extinction.component_mul(&transmittance));
+            MediumInteraction::Scatter {
+                distance,
+                weight: transmittance.component_mul(&self.scattering) / pdf,
+            }
+        } else {
+            let transmittance = self.transmittance(max_distance);
+            let pdf = average(&transmittance);
+            MediumInteraction::Pass {
+                weight: if pdf == 0.0 {
+                    Vector3D::default()
+                } else {
+                    transmittance / pdf
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::material::Material;
+use crate::scene::object::Object;
+
+/// Precision of the stored positions, `f64` for scenes with large coordinates
+/// unless the `f32-positions` feature is enabled.
+#[cfg(not(feature = "f32-positions"))]
+pub type PositionFloat = f64;
+#[cfg(feature = "f32-positions")]
+pub type PositionFloat = f32;
+
+/// Triangles of the scene, which refer to shared vertex attributes and materials by index.
+/// Directions are stored in `f32`, positions in `PositionFloat`. Computations on them are
+/// done in `f64` either way.
+#[derive(Serialize, Deserialize)]
+pub struct Mesh {
+    pub positions: Vec<Vector3D<PositionFloat>>,
+    pub normals: Vec<Vector3D<f32>>,
+    /// Directions in which the texture coordinates grow around each texture mapped vertex,
+    /// for normal maps
+    pub tangents: Vec<Vector3D<f32>>,
+    pub bitangents: Vec<Vector3D<f32>>,
+    pub texture_coordinates: Vec<[f64; 2]>,
+    /// Not cached, they are read from the .mtl files again
+    #[serde(skip)]
+    pub materials: Vec<Material>,
+    pub triangles: Vec<Triangle>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Triangle {
+    /// Indices of the positions and normals
+    pub vertices: [u32; 3],
+    /// Indices of the texture coordinates, if the triangle has them
+    pub texture_coordinates: Option<[u32; 3]>,
+    /// Indices of the tangents and bitangents, if the triangle has texture coordinates
+    pub tangents: Option<[u32; 3]>,
+    pub material: u32,
+    pub object_id: u32,
+    /// Index of the area light in the scene lights, for emissive triangles
+    pub light: Option<u32>,
+}
+
+impl Mesh {
+    pub fn object(&self, triangle: usize) -> Object<'_> {
+        Object::new(self, &self.triangles[triangle])
+    }
+
+    /// Corners of a triangle.
+    pub fn points(&self, triangle: &Triangle) -> [Vector3D; 3] {
+        triangle
+            .vertices
+            .map(|vertex| self.positions[vertex as usize].cast())
+    }
+}
@@ -0,0 +1,118 @@
+use std::f64::consts::PI;
+
+use crate::geometry::vector::Vector3D;
+
+/// Below this alpha the distribution is treated as a perfect mirror.
+static MIN_ALPHA: f64 = 1e-3;
+
+/// Trowbridge-Reitz (GGX) microfacet distribution with Smith height-correlated shadowing.
+/// All directions are in the local shading frame, where the normal is +z,
+/// and point away from the surface.
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    pub fn new(roughness: f64) -> Self {
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < MIN_ALPHA
+    }
+
+    fn alpha(&self) -> f64 {
+        self.alpha.max(MIN_ALPHA)
+    }
+
+    pub fn distribution(&self, half: &Vector3D) -> f64 {
+        if half.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha2 = self.alpha() * self.alpha();
+        let denominator = half.z * half.z * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, direction: &Vector3D) -> f64 {
+        let cos2 = direction.z * direction.z;
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha() * self.alpha() * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn masking(&self, direction: &Vector3D) -> f64 {
+        1.0 / (1.0 + self.lambda(direction))
+    }
+
+    pub fn masking_shadowing(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64 {
+        1.0 / (1.0 + self.lambda(outgoing) + self.lambda(incoming))
+    }
+
+    /// Samples a microfacet normal visible from `outgoing` (Heitz 2018)
+    /// with `u1`, `u2` uniform in [0, 1).
+    pub fn sample_visible_normal(&self, outgoing: &Vector3D, u1: f64, u2: f64) -> Vector3D {
+        let alpha = self.alpha();
+        let stretched = Vector3D {
+            x: alpha * outgoing.x,
+            y: alpha * outgoing.y,
+            z: outgoing.z,
+        }
+        .normalize();
+
+        let length2 = stretched.x * stretched.x + stretched.y * stretched.y;
+        let first_axis = if length2 > 0.0 {
+            &Vector3D::from([-stretched.y, stretched.x, 0.0]) / length2.sqrt()
+        } else {
+            Vector3D::from([1.0, 0.0, 0.0])
+        };
+        let second_axis = stretched.cross(&first_axis);
+
+        let radius = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let t1 = radius * phi.cos();
+        let s = 0.5 * (1.0 + stretched.z);
+        let t2 = (1.0 - s) * (1.0 - t1 * t1).sqrt() + s * radius * phi.sin();
+
+        let normal = &first_axis * t1
+            + &second_axis * t2
+            + &stretched * (1.0 - t1 * t1 - t2 * t2).max(0.0).sqrt();
+
+        Vector3D {
+            x: alpha * normal.x,
+            y: alpha * normal.y,
+            z: normal.z.max(0.0),
+        }
+        .normalize()
+    }
+
+    /// Density of `incoming` when it is produced by reflecting `outgoing`
+    /// about a visible normal from `sample_visible_normal`.
+    pub fn pdf(&self, outgoing: &Vector3D, incoming: &Vector3D) -> f64 {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return 0.0;
+        }
+        let half = (outgoing + incoming).normalize();
+        self.masking(outgoing) * self.distribution(&half) / (4.0 * outgoing.z)
+    }
+
+    /// Specular BRDF value `D * G * F / (4 * cos_o * cos_i)`.
+    pub fn evaluate(&self, outgoing: &Vector3D, incoming: &Vector3D, f0: &Vector3D) -> Vector3D {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return Vector3D::default();
+        }
+        let half = (outgoing + incoming).normalize();
+        fresnel_schlick(f0, outgoing * &half)
+            * (self.distribution(&half) * self.masking_shadowing(outgoing, incoming)
+                / (4.0 * outgoing.z * incoming.z))
+    }
+}
+
+pub fn fresnel_schlick(f0: &Vector3D, cos: f64) -> Vector3D {
+    let weight = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+    f0 * (1.0 - weight) + Vector3D::from([weight, weight, weight])
+}
@@ -1,33 +1,366 @@
-use std::sync::Arc;
-
-use crate::geometry::polygon::Polygon;
+use crate::geometry::bounding_box::BoundingBox;
+use crate::geometry::intersection::Intersection;
+use crate::geometry::polygon::{interpolate, position_derivatives};
+use crate::geometry::ray::{Ray, RayDifferential};
 use crate::geometry::vector::Vector3D;
+use crate::geometry::ShearedRay;
 use crate::scene::material::Material;
+use crate::scene::mesh::{Mesh, Triangle};
+use crate::scene::texture::{ImageTexture, SurfaceDifferentials, SurfacePoint};
 
-pub struct Object {
-    pub polygon: Polygon,
-    pub material: Arc<Material>,
+/// Triangle of the scene mesh together with the data it refers to.
+#[derive(Clone, Copy)]
+pub struct Object<'a> {
+    mesh: &'a Mesh,
+    triangle: &'a Triangle,
+    pub material: &'a Material,
+    pub object_id: usize,
+    /// Index of the area light in the scene lights, for emissive objects
+    pub light: Option<usize>,
 }
 
-pub struct PseudoObject {
-    pub material: Arc<Material>,
-    pub first_vertex_idx: usize,
-    pub second_vertex_idx: usize,
-    pub third_vertex_idx: usize,
+impl<'a> Object<'a> {
+    pub fn new(mesh: &'a Mesh, triangle: &'a Triangle) -> Self {
+        Self {
+            mesh,
+            triangle,
+            material: &mesh.materials[triangle.material as usize],
+            object_id: triangle.object_id as usize,
+            light: triangle.light.map(|light| light as usize),
+        }
+    }
+
+    pub fn points(&self) -> [Vector3D; 3] {
+        self.mesh.points(self.triangle)
+    }
+
+    /// Values of a per vertex attribute of the mesh at the corners.
+    fn corner_values(&self, values: &[Vector3D<f32>]) -> [Vector3D; 3] {
+        self.triangle
+            .vertices
+            .map(|vertex| values[vertex as usize].cast())
+    }
+
+    /// Values of a per texture mapped vertex attribute of the mesh at the corners.
+    fn corner_tangent_values(&self, values: &[Vector3D<f32>]) -> Option<[Vector3D; 3]> {
+        Some(
+            self.triangle
+                .tangents?
+                .map(|vertex| values[vertex as usize].cast()),
+        )
+    }
+
+    /// Vertex normals interpolated at `intersection`, not flipped towards the ray.
+    fn interpolated_normal(&self, intersection: &Intersection) -> Vector3D {
+        interpolate(
+            &self.corner_values(&self.mesh.normals),
+            intersection.barycentric,
+        )
+        .normalize()
+    }
+
+    fn corner_texture_coordinates(&self) -> Option<[[f64; 2]; 3]> {
+        Some(
+            self.triangle
+                .texture_coordinates?
+                .map(|index| self.mesh.texture_coordinates[index as usize]),
+        )
+    }
+
+    pub fn bounds(&self) -> BoundingBox {
+        let [first, second, third] = self.points();
+        BoundingBox::from_point(&first)
+            .union_point(&second)
+            .union_point(&third)
+    }
+
+    /// Hit of the ray with the vertex normals interpolated, facing the ray.
+    pub fn intersect(&self, ray: &ShearedRay) -> Option<Intersection> {
+        let mut intersection = ray.intersect(self.points().each_ref())?;
+        let normal = self.interpolated_normal(&intersection);
+        intersection.normal = if &normal * ray.direction() > 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        Some(intersection)
+    }
+
+    /// Texture coordinates at `intersection`, for meshes that have them.
+    pub fn texture_coordinates(&self, intersection: &Intersection) -> Option<[f64; 2]> {
+        let [first, second, third] = self.corner_texture_coordinates()?;
+        let [u, v] = intersection.barycentric;
+        Some([0, 1].map(|i| first[i] * (1.0 - u - v) + second[i] * u + third[i] * v))
+    }
+
+    /// Point at `intersection` for texture lookups, with the `differential` of the ray there.
+    pub fn surface_point(
+        &self,
+        intersection: &Intersection,
+        differential: Option<RayDifferential>,
+    ) -> SurfacePoint {
+        SurfacePoint {
+            position: intersection.position.clone(),
+            coordinates: self.texture_coordinates(intersection),
+            differentials: differential.map(|differential| SurfaceDifferentials {
+                coordinates: self.coordinate_derivatives(&differential.origin),
+                position: differential.origin,
+            }),
+        }
+    }
+
+    /// Texture coordinate changes for the position changes along the polygon,
+    /// solved in the least squares sense.
+    fn coordinate_derivatives(&self, position_derivatives_xy: &[Vector3D; 2]) -> [[f64; 2]; 2] {
+        let Some((dpdu, dpdv)) = self
+            .corner_texture_coordinates()
+            .and_then(|coordinates| position_derivatives(self.points().each_ref(), &coordinates))
+        else {
+            return [[0.0; 2]; 2];
+        };
+
+        let (uu, uv, vv) = (&dpdu * &dpdu, &dpdu * &dpdv, &dpdv * &dpdv);
+        let determinant = uu * vv - uv * uv;
+        if determinant.abs() < 1e-12 {
+            return [[0.0; 2]; 2];
+        }
+        position_derivatives_xy.clone().map(|derivative| {
+            let (pu, pv) = (&derivative * &dpdu, &derivative * &dpdv);
+            [
+                (vv * pu - uv * pv) / determinant,
+                (uu * pv - uv * pu) / determinant,
+            ]
+        })
+    }
+
+    /// Interpolated normal at the `intersection` of `ray` perturbed by the normal and bump maps
+    /// of the material, filtered over the ray footprint. Perturbed normals facing away from
+    /// the ray origin are dropped.
+    pub fn shading_normal(&self, intersection: &Intersection, ray: &Ray) -> Vector3D {
+        let normal = &intersection.normal;
+        if self.material.normal_map.is_none() && self.material.bump_map.is_none() {
+            return normal.clone();
+        }
+        let surface = self.surface_point(intersection, ray.differential_at(intersection));
+        let Some(coordinates) = surface.coordinates else {
+            return normal.clone();
+        };
+        let differentials = surface.differentials.as_ref();
+
+        let mut perturbed = normal.clone();
+        if let Some(ref normal_map) = self.material.normal_map {
+            let texel = normal_map.lookup_at(coordinates, differentials);
+            if let Some(mapped) = self.map_normal(&texel, intersection) {
+                perturbed = mapped;
+            }
+        }
+        if let Some((ref bump_map, scale)) = self.material.bump_map {
+            if let Some(bumped) =
+                self.bump_normal(bump_map, scale, &perturbed, coordinates, differentials)
+            {
+                perturbed = bumped;
+            }
+        }
+
+        if &perturbed * &ray.direction < 0.0 {
+            perturbed
+        } else {
+            normal.clone()
+        }
+    }
+
+    /// Normal given in the tangent frame by a `texel` of a normal map.
+    fn map_normal(&self, texel: &Vector3D, intersection: &Intersection) -> Option<Vector3D> {
+        // The tangents follow the surface rather than the ray, so the frame is built around the
+        // unflipped normal and the mapped normal flipped like the interpolated one afterwards
+        let normal = &self.interpolated_normal(intersection);
+        let flipped = normal * &intersection.normal < 0.0;
+        let tangent = interpolate(
+            &self.corner_tangent_values(&self.mesh.tangents)?,
+            intersection.barycentric,
+        );
+        let tangent = &tangent - normal * (normal * &tangent);
+        if tangent.len() < 1e-9 {
+            return None;
+        }
+        let tangent = tangent.normalize();
+        let bitangent = interpolate(
+            &self.corner_tangent_values(&self.mesh.bitangents)?,
+            intersection.barycentric,
+        );
+        let bitangent =
+            &bitangent - normal * (normal * &bitangent) - &tangent * (&tangent * &bitangent);
+        if bitangent.len() < 1e-9 {
+            return None;
+        }
+
+        let mapped = texel * 2.0 - Vector3D::from([1.0, 1.0, 1.0]);
+        let mapped =
+            (tangent * mapped.x + bitangent.normalize() * mapped.y + normal * mapped.z).normalize();
+        Some(if flipped { -mapped } else { mapped })
+    }
+
+    /// Normal of the surface displaced along `normal` by the height map (Blinn 1978),
+    /// differentiated over the pixel footprint where it is known.
+    fn bump_normal(
+        &self,
+        bump_map: &ImageTexture,
+        scale: f64,
+        normal: &Vector3D,
+        [u, v]: [f64; 2],
+        differentials: Option<&SurfaceDifferentials>,
+    ) -> Option<Vector3D> {
+        let (dpdu, dpdv) = position_derivatives(
+            self.points().each_ref(),
+            &self.corner_texture_coordinates()?,
+        )?;
+
+        let height = |u: f64, v: f64| {
+            let texel = bump_map.lookup_at([u, v], differentials);
+            (texel.x + texel.y + texel.z) / 3.0 * scale
+        };
+        let [[dudx, dvdx], [dudy, dvdy]] =
+            differentials.map_or([[0.0; 2]; 2], |differentials| differentials.coordinates);
+        let du = (0.5 * (dudx.abs() + dudy.abs())).max(0.5 / bump_map.width as f64);
+        let dv = (0.5 * (dvdx.abs() + dvdy.abs())).max(0.5 / bump_map.height as f64);
+        let center = height(u, v);
+        let dhdu = (height(u + du, v) - center) / du;
+        let dhdv = (height(u, v + dv) - center) / dv;
+
+        let bumped = (dpdu + normal * dhdu).cross(dpdv + normal * dhdv);
+        if bumped.len() < 1e-12 {
+            return None;
+        }
+        let bumped = bumped.normalize();
+        Some(if &bumped * normal < 0.0 {
+            -bumped
+        } else {
+            bumped
+        })
+    }
 }
 
-impl PseudoObject {
-    pub fn build_object(&self, vertices: &[Vector3D], normals: &[Vector3D]) -> Object {
-        Object {
-            material: Arc::clone(&self.material),
-            polygon: Polygon {
-                first_point: vertices[self.first_vertex_idx].clone(),
-                second_point: vertices[self.second_vertex_idx].clone(),
-                third_point: vertices[self.third_vertex_idx].clone(),
-                first_normal: normals[self.first_vertex_idx].clone(),
-                second_normal: normals[self.second_vertex_idx].clone(),
-                third_normal: normals[self.third_vertex_idx].clone(),
-            },
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::scene::Scene;
+
+    fn hit_along_z<'a>(scene: &'a Scene, at: [f64; 2], from_z: f64) -> (Intersection, Object<'a>) {
+        let ray = Ray {
+            from: Vector3D::from([at[0], at[1], from_z]),
+            direction: Vector3D::from([0.0, 0.0, -from_z.signum()]),
+            inside: false,
+            differential: None,
+        };
+        scene
+            .bvh
+            .closest_hit(&scene.mesh, &ray, |_, _| true)
+            .unwrap()
+    }
+
+    fn assert_close(a: &Vector3D, b: &Vector3D) {
+        assert!(
+            (a - b).len() < 1e-2,
+            "{:?} vs {:?}",
+            [a.x, a.y, a.z],
+            [b.x, b.y, b.z]
+        );
+    }
+
+    #[test]
+    fn mirrored_texture_coordinates_get_their_own_tangents() {
+        // The second triangle shares an edge and its texture coordinates with the first one,
+        // but its u grows towards -x
+        let scene = Scene::from_text(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv -1 0 0\n\
+             vt 0 0\nvt 1 0\nvt 0 1\n\
+             f 1/1 2/2 3/3\nf 1/1 3/3 4/2\n",
+            "",
+        );
+        for (at, tangent) in [([0.2, 0.2], 1.0), ([-0.2, 0.2], -1.0)] {
+            let (_, object) = hit_along_z(&scene, at, 1.0);
+            for corner in object.corner_tangent_values(&scene.mesh.tangents).unwrap() {
+                assert_close(&corner.normalize(), &Vector3D::from([tangent, 0.0, 0.0]));
+            }
         }
     }
+
+    #[test]
+    fn normal_maps_tilt_the_same_way_seen_from_either_side() {
+        let map_path = std::env::temp_dir().join(format!("normal_{}.png", std::process::id()));
+        // Tilted towards +u, which is +x
+        RgbImage::from_pixel(1, 1, Rgb([191, 128, 255]))
+            .save(&map_path)
+            .unwrap();
+        let scene = Scene::from_text(
+            "mtllib materials.mtl\n\
+             v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             usemtl mapped\nf 1/1 2/2 3/3 4/4\n",
+            &format!("newmtl mapped\nnorm {}\n", map_path.display()),
+        );
+        std::fs::remove_file(&map_path).unwrap();
+
+        let tilted = Vector3D::from([0.5, 0.0, 1.0]).normalize();
+        for from_z in [1.0, -1.0] {
+            let (intersection, object) = hit_along_z(&scene, [0.0, 0.0], from_z);
+            let ray = Ray {
+                from: Vector3D::from([0.0, 0.0, from_z]),
+                direction: Vector3D::from([0.0, 0.0, -from_z]),
+                inside: false,
+                differential: None,
+            };
+            let normal = object.shading_normal(&intersection, &ray);
+            assert_close(&normal, &(&tilted * from_z));
+        }
+    }
+
+    #[test]
+    fn normal_maps_are_filtered_over_the_ray_footprint() {
+        let map_path = std::env::temp_dir().join(format!("stripes_{}.png", std::process::id()));
+        // Columns tilted towards +x and -x in turn, which average out
+        RgbImage::from_fn(4, 4, |x, _| {
+            Rgb([if x % 2 == 0 { 191 } else { 64 }, 128, 255])
+        })
+        .save(&map_path)
+        .unwrap();
+        let scene = Scene::from_text(
+            "mtllib materials.mtl\n\
+             v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             usemtl mapped\nf 1/1 2/2 3/3 4/4\n",
+            &format!("newmtl mapped\nnorm {}\n", map_path.display()),
+        );
+        std::fs::remove_file(&map_path).unwrap();
+
+        let shading_normal = |differential: Option<RayDifferential>| {
+            let ray = Ray {
+                from: Vector3D::from([-0.75, -0.75, 1.0]),
+                direction: Vector3D::from([0.0, 0.0, -1.0]),
+                inside: false,
+                differential,
+            };
+            let (intersection, object) = scene
+                .bvh
+                .closest_hit(&scene.mesh, &ray, |_, _| true)
+                .unwrap();
+            object.shading_normal(&intersection, &ray)
+        };
+        let tilted = Vector3D::from([0.5, 0.0, 1.0]).normalize();
+        assert_close(&shading_normal(None), &tilted);
+        // Neighbouring pixels are as far apart as the whole quad
+        let wide = RayDifferential {
+            origin: [
+                Vector3D::from([2.0, 0.0, 0.0]),
+                Vector3D::from([0.0, 2.0, 0.0]),
+            ],
+            direction: [Vector3D::default(), Vector3D::default()],
+        };
+        assert_close(
+            &shading_normal(Some(wide)),
+            &Vector3D::from([0.0, 0.0, 1.0]),
+        );
+    }
 }
@@ -0,0 +1,239 @@
+use std::f64::consts::PI;
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::geometry::frame::Frame;
+use crate::geometry::ray::Ray;
+use crate::geometry::vector::Vector3D;
+use crate::scene::sky::SkySample;
+
+/// Angular radius of the sun disk, radians
+static SUN_ANGULAR_RADIUS: f64 = 0.00465;
+/// Luminance of the sun outside the atmosphere, kcd/m²
+static SUN_LUMINANCE: f64 = 1.6e6;
+
+/// Resolution of the numeric integration of sky irradiance on the ground.
+static IRRADIANCE_STEPS: usize = 32;
+
+/// Perez luminance distribution `(1 + A e^(B / cos θ)) (1 + C e^(D γ) + E cos² γ)`.
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn evaluate(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let cos_gamma = gamma.cos();
+        (1.0 + self.a * (self.b / cos_theta.max(1e-3)).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+fn polynomial(coefficients: [f64; 4], theta: f64) -> f64 {
+    coefficients
+        .iter()
+        .fold(0.0, |value, coefficient| value * theta + coefficient)
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Vector3D {
+    if y <= 0.0 {
+        return Vector3D::default();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vector3D {
+        x: (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        y: (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        z: (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    }
+}
+
+/// Transmittance of the atmosphere along the sun direction for a wavelength in micrometers,
+/// Rayleigh scattering and aerosols with Ångström's formula.
+fn sun_transmittance(wavelength: f64, theta_sun: f64, turbidity: f64) -> f64 {
+    let relative_optical_mass =
+        1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let rayleigh = (-0.008735 * wavelength.powf(-4.08) * relative_optical_mass).exp();
+    let aerosol = (-beta * wavelength.powf(-1.3) * relative_optical_mass).exp();
+    rayleigh * aerosol
+}
+
+/// Analytic daylight model by Preetham et al. 1999 with a sun disk light.
+/// +y is up, radiance is in kcd/m².
+pub struct PhysicalSky {
+    sun_direction: Vector3D,
+    sun_radiance: Vector3D,
+    ground_radiance: Vector3D,
+    zenith: (f64, f64, f64),
+    perez: (Perez, Perez, Perez),
+}
+
+impl PhysicalSky {
+    pub fn new(sun_direction: Vector3D, turbidity: f64, ground_albedo: Vector3D) -> Self {
+        let sun_direction = sun_direction.normalize();
+        // The model isn't defined for the sun below the horizon
+        let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+        let t = turbidity;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = t * t * polynomial([0.00166, -0.00375, 0.00209, 0.0], theta_sun)
+            + t * polynomial([-0.02903, 0.06377, -0.03202, 0.00394], theta_sun)
+            + polynomial([0.11693, -0.21196, 0.06052, 0.25886], theta_sun);
+        let zenith_y = t * t * polynomial([0.00275, -0.00610, 0.00317, 0.0], theta_sun)
+            + t * polynomial([-0.04214, 0.08970, -0.04153, 0.00516], theta_sun)
+            + polynomial([0.15346, -0.26756, 0.06670, 0.26688], theta_sun);
+
+        let perez = (
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        );
+
+        let sun_radiance = if sun_direction.y > 0.0 {
+            Vector3D {
+                x: sun_transmittance(0.680, theta_sun, t),
+                y: sun_transmittance(0.550, theta_sun, t),
+                z: sun_transmittance(0.440, theta_sun, t),
+            } * SUN_LUMINANCE
+        } else {
+            Vector3D::default()
+        };
+
+        let mut sky = Self {
+            sun_direction,
+            sun_radiance,
+            ground_radiance: Vector3D::default(),
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+            perez,
+        };
+        sky.ground_radiance = sky.ground_irradiance().component_mul(&ground_albedo) / PI;
+        sky
+    }
+
+    fn sun_cos_radius() -> f64 {
+        SUN_ANGULAR_RADIUS.cos()
+    }
+
+    fn sun_solid_angle() -> f64 {
+        2.0 * PI * (1.0 - Self::sun_cos_radius())
+    }
+
+    /// Irradiance on a horizontal surface from the sun and the sky dome.
+    fn ground_irradiance(&self) -> Vector3D {
+        let mut irradiance =
+            &self.sun_radiance * (Self::sun_solid_angle() * self.sun_direction.y.max(0.0));
+
+        let d_theta = PI / 2.0 / IRRADIANCE_STEPS as f64;
+        let d_phi = 2.0 * PI / (4 * IRRADIANCE_STEPS) as f64;
+        for i in 0..IRRADIANCE_STEPS {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..4 * IRRADIANCE_STEPS {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vector3D {
+                    x: theta.sin() * phi.cos(),
+                    y: theta.cos(),
+                    z: theta.sin() * phi.sin(),
+                };
+                irradiance += self.dome(&direction) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+
+        irradiance
+    }
+
+    /// Radiance of the sky dome without the sun, for directions above the horizon.
+    fn dome(&self, direction: &Vector3D) -> Vector3D {
+        let theta_sun = self.sun_direction.y.clamp(0.0, 1.0).acos();
+        let gamma = (direction * &self.sun_direction).clamp(-1.0, 1.0).acos();
+        let cos_theta = direction.y;
+
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith;
+        let (perez_luminance, perez_x, perez_y) = &self.perez;
+        let luminance = zenith_luminance * perez_luminance.evaluate(cos_theta, gamma)
+            / perez_luminance.evaluate(1.0, theta_sun);
+        let x = zenith_x * perez_x.evaluate(cos_theta, gamma) / perez_x.evaluate(1.0, theta_sun);
+        let y = zenith_y * perez_y.evaluate(cos_theta, gamma) / perez_y.evaluate(1.0, theta_sun);
+
+        xyy_to_rgb(x, y, luminance)
+    }
+
+    /// Radiance of the sky dome and the ground, but not the sun.
+    pub fn trace_without_sun(&self, ray: &Ray) -> Vector3D {
+        if ray.direction.y < 0.0 {
+            self.ground_radiance.clone()
+        } else {
+            self.dome(&ray.direction)
+        }
+    }
+
+    fn is_in_sun(&self, direction: &Vector3D) -> bool {
+        direction * &self.sun_direction >= Self::sun_cos_radius()
+    }
+
+    pub fn trace_sun(&self, ray: &Ray) -> Vector3D {
+        if self.is_in_sun(&ray.direction) {
+            self.sun_radiance.clone()
+        } else {
+            Vector3D::default()
+        }
+    }
+
+    pub fn trace(&self, ray: &Ray) -> Vector3D {
+        self.trace_without_sun(ray) + self.trace_sun(ray)
+    }
+
+    /// Samples a direction towards the sun disk uniformly.
+    pub fn sample_sun(&self, rng: &mut ThreadRng) -> Option<SkySample> {
+        if self.sun_direction.y <= 0.0 {
+            return None;
+        }
+
+        let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - Self::sun_cos_radius());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let direction = Frame::from_normal(&self.sun_direction).to_world(&Vector3D {
+            x: sin_theta * phi.cos(),
+            y: sin_theta * phi.sin(),
+            z: cos_theta,
+        });
+
+        Some(SkySample {
+            direction,
+            radiance: self.sun_radiance.clone(),
+            pdf: 1.0 / Self::sun_solid_angle(),
+        })
+    }
+
+    /// Density of `sample_sun` returning `direction`.
+    pub fn sun_pdf(&self, direction: &Vector3D) -> f64 {
+        if self.sun_direction.y > 0.0 && self.is_in_sun(direction) {
+            1.0 / Self::sun_solid_angle()
+        } else {
+            0.0
+        }
+    }
+}
@@ -1,19 +1,42 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
-use std::path::Path;
+use std::panic;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::thread;
 
 use anyhow::{anyhow, Result};
+use chunk::{Chunk, Statement};
 use log::{debug, info};
+use memmap2::Mmap;
 
+use crate::geometry::polygon::position_derivatives;
 use crate::geometry::vector::Vector3D;
-use crate::scene::light::Light;
-use crate::scene::material::Material;
-use crate::scene::object::PseudoObject;
-use crate::scene::CubeMap;
+use crate::scene::bsdf::dielectric::Dispersion;
+use crate::scene::bvh::Bvh;
+use crate::scene::cache::SceneCache;
+use crate::scene::cube_map::{CubeMap, CubeMapLayout};
+use crate::scene::environment_map::EnvironmentMap;
+use crate::scene::light::area::AreaLight;
+use crate::scene::light::bvh::LightBvh;
+use crate::scene::light::directional::DirectionalLight;
+use crate::scene::light::point::PointLight;
+use crate::scene::light::spot::SpotLight;
+use crate::scene::light::{Attenuation, Light};
+use crate::scene::material::{Material, TexturedParameter};
+use crate::scene::medium::grid::{GridMedium, VoxelGrid};
+use crate::scene::medium::homogeneous::HomogeneousMedium;
+use crate::scene::medium::{HenyeyGreenstein, Medium};
+use crate::scene::mesh::{Mesh, Triangle};
+use crate::scene::physical_sky::PhysicalSky;
+use crate::scene::sky::Sky;
+use crate::scene::texture::noise::MAX_OCTAVES;
+use crate::scene::texture::{ImageTexture, Mapping, Pattern, Texture, TextureTransform};
 use crate::scene::Scene;
 
+mod chunk;
+
 fn read_point(triplet: &[&str]) -> Result<Vector3D> {
     if triplet.len() != 3 {
         return Err(anyhow!("Point must have 3 numbers"));
@@ -27,31 +50,264 @@ fn read_point(triplet: &[&str]) -> Result<Vector3D> {
     Ok(vec)
 }
 
-fn read_indices_pairs(face_elements: &[&str]) -> Vec<(isize, isize)> {
-    let mut result = vec![];
-
+/// Vertex, texture coordinates and normal indices of the face elements, 0 where missing.
+fn read_face_indices(face_elements: &[&str], result: &mut Vec<(isize, isize, isize)>) {
+    let parse_optional = |index: &str| {
+        if index.is_empty() {
+            0
+        } else {
+            index.parse().unwrap()
+        }
+    };
     for &face_element in face_elements {
         match face_element.split('/').collect::<Vec<&str>>()[..] {
-            [point, .., normal] => result.push((point.parse().unwrap(), normal.parse().unwrap())),
-            [point] => result.push((point.parse().unwrap(), 0)),
+            [point] => result.push((point.parse().unwrap(), 0, 0)),
+            [point, texture] => result.push((point.parse().unwrap(), parse_optional(texture), 0)),
+            [point, texture, normal] => result.push((
+                point.parse().unwrap(),
+                parse_optional(texture),
+                normal.parse().unwrap(),
+            )),
             _ => panic!("Failed to parse indices"),
         }
     }
+}
+
+/// Per vertex sums of the normals and texture space directions of the faces around it.
+struct VertexAttributes {
+    normals: Vec<Vector3D>,
+    /// Summed over the triangles around each texture mapped vertex
+    tangents: Vec<Vector3D>,
+    bitangents: Vec<Vector3D>,
+    /// Tangent index of each position, texture coordinates and handedness, so that vertices on
+    /// UV seams and mirrored UVs get a tangent frame for each side
+    tangent_indices: HashMap<(usize, usize, bool), usize>,
+}
+
+impl VertexAttributes {
+    fn new(vertices: usize) -> Self {
+        Self {
+            normals: vec![Vector3D::default(); vertices],
+            tangents: vec![],
+            bitangents: vec![],
+            tangent_indices: HashMap::new(),
+        }
+    }
+
+    fn tangent_index(&mut self, vertex: (usize, usize, bool)) -> usize {
+        *self.tangent_indices.entry(vertex).or_insert_with(|| {
+            self.tangents.push(Vector3D::default());
+            self.bitangents.push(Vector3D::default());
+            self.tangents.len() - 1
+        })
+    }
+}
+
+fn read_texture_coordinates(body: &[&str]) -> Result<[f64; 2]> {
+    match body {
+        [u] => Ok([u.parse()?, 0.0]),
+        [u, v] | [u, v, _] => Ok([u.parse()?, v.parse()?]),
+        _ => Err(anyhow!("Texture coordinates must have 1 to 3 numbers")),
+    }
+}
+
+fn read_texture(texture_path: &Path) -> Result<ImageTexture> {
+    read_texture_with(ImageTexture::open, texture_path)
+}
+
+fn read_texture_with(
+    open: impl Fn(&Path) -> Result<ImageTexture>,
+    texture_path: &Path,
+) -> Result<ImageTexture> {
+    match open(texture_path) {
+        Ok(texture) => Ok(texture),
+        Err(err) => Err(err.context(format!("reading texture {}", texture_path.display()))),
+    }
+}
+
+/// `Texture <name> <pattern> <arguments> [options]`, where the pattern is `image <file>` or
+/// `checker`, `noise`, `wood` or `marble` with two colors. Options are `-uv` or `-object` mapping,
+/// `-scale <x> <y> <z>`, `-rotate <degrees> <axis x> <axis y> <axis z>`, `-offset <x> <y> <z>`
+/// and `-octaves <n>` of noise.
+fn read_texture_definition(body: &[&str], directory: &Path) -> Result<(String, Texture)> {
+    // Options are words after a dash, unlike negative numbers
+    let options_start = body
+        .iter()
+        .position(|token| {
+            token.starts_with('-') && token[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+        })
+        .unwrap_or(body.len());
+    let (arguments, mut options) = body.split_at(options_start);
+
+    let mut octaves = 6;
+    let mut mapping = None;
+    let mut transform = TextureTransform::default();
+    while let Some((option, rest)) = options.split_first() {
+        options = match (*option, rest) {
+            ("-uv", rest) => {
+                mapping = Some(Mapping::Uv);
+                rest
+            }
+            ("-object", rest) => {
+                mapping = Some(Mapping::Object);
+                rest
+            }
+            ("-scale", [x, y, z, rest @ ..]) => {
+                transform.scale = read_point(&[x, y, z])?;
+                rest
+            }
+            ("-rotate", [degrees, x, y, z, rest @ ..]) => {
+                let degrees: f64 = degrees.parse()?;
+                transform.rotation = (read_point(&[x, y, z])?.normalize(), degrees.to_radians());
+                rest
+            }
+            ("-offset", [x, y, z, rest @ ..]) => {
+                transform.offset = read_point(&[x, y, z])?;
+                rest
+            }
+            ("-octaves", [count, rest @ ..]) => {
+                octaves = count.parse::<usize>()?.min(MAX_OCTAVES);
+                rest
+            }
+            _ => return Err(anyhow!("Unknown texture option {}", option)),
+        };
+    }
 
-    result
+    let (name, pattern, colors) = match arguments {
+        [name, "image", filename] => (
+            name,
+            Pattern::Image(read_texture_with(
+                ImageTexture::open_srgb,
+                &directory.join(filename),
+            )?),
+            Default::default(),
+        ),
+        [name, pattern, colors @ ..] if colors.len() == 6 => {
+            let pattern = match *pattern {
+                "checker" => Pattern::Checkerboard,
+                "noise" => Pattern::Noise { octaves },
+                "wood" => Pattern::Wood { octaves },
+                "marble" => Pattern::Marble { octaves },
+                _ => return Err(anyhow!("Unknown texture pattern {}", pattern)),
+            };
+            (
+                name,
+                pattern,
+                [read_point(&colors[..3])?, read_point(&colors[3..])?],
+            )
+        }
+        _ => {
+            return Err(anyhow!(
+                "Texture must be <name> image <file> or <name> <pattern> <color> <color>"
+            ))
+        }
+    };
+
+    // Images are usually laid out over the texture coordinates, patterns fill space
+    let mapping = mapping.unwrap_or(match pattern {
+        Pattern::Image(_) => Mapping::Uv,
+        _ => Mapping::Object,
+    });
+    Ok((
+        name.to_string(),
+        Texture {
+            pattern,
+            colors,
+            mapping,
+            transform,
+        },
+    ))
+}
+
+/// Height map with its `-bm <scale>` option, which can come before or after the file name.
+fn read_bump_map(body: &[&str], directory: &Path) -> Result<(ImageTexture, f64)> {
+    let mut scale = 1.0;
+    let mut filename = None;
+    let mut tokens = body.iter();
+    while let Some(&token) = tokens.next() {
+        match token {
+            "-bm" => match tokens.next() {
+                Some(value) => scale = value.parse()?,
+                None => return Err(anyhow!("-bm must be followed by a scale")),
+            },
+            _ if token.starts_with('-') => {
+                return Err(anyhow!("Unknown bump map option {}", token));
+            }
+            _ if filename.is_none() => filename = Some(token),
+            _ => return Err(anyhow!("Bump map must be [-bm <scale>] <file>")),
+        }
+    }
+    let Some(filename) = filename else {
+        return Err(anyhow!("Bump map must be [-bm <scale>] <file>"));
+    };
+    Ok((read_texture(&directory.join(filename))?, scale))
 }
 
 fn add_line_context(error: anyhow::Error, file: &Path, line: usize) -> anyhow::Error {
     error.context(format!("on line {} of {}", line, file.display()))
 }
 
-fn read_materials(materials_path: &Path) -> Result<HashMap<String, Arc<Material>>> {
+fn read_numbers(numbers: &[&str]) -> Result<Vec<f64>> {
+    numbers.iter().map(|number| Ok(number.parse()?)).collect()
+}
+
+fn read_cauchy(body: &[&str]) -> Result<Dispersion> {
+    if body.len() != 2 && body.len() != 3 {
+        return Err(anyhow!(
+            "Cauchy dispersion must have 2 or 3 coefficients, got {}",
+            body.len()
+        ));
+    }
+    let coefficients = read_numbers(body)?;
+    Ok(Dispersion::Cauchy {
+        a: coefficients[0],
+        b: coefficients[1],
+        c: coefficients.get(2).copied().unwrap_or(0.0),
+    })
+}
+
+fn read_sellmeier(body: &[&str]) -> Result<Dispersion> {
+    if body.len() != 6 {
+        return Err(anyhow!(
+            "Sellmeier dispersion must have 6 coefficients, got {}",
+            body.len()
+        ));
+    }
+    let coefficients = read_numbers(body)?;
+    Ok(Dispersion::Sellmeier {
+        b: [coefficients[0], coefficients[1], coefficients[2]],
+        c: [coefficients[3], coefficients[4], coefficients[5]],
+    })
+}
+
+/// Absorption coefficients letting through the `Tf` fraction of light per scene unit.
+fn read_transmission_filter(body: &[&str]) -> Result<Vector3D> {
+    let filter = read_point(body)?;
+    let absorption = |transmission: f64| {
+        if transmission > 0.0 && transmission <= 1.0 {
+            Ok(-transmission.ln())
+        } else {
+            Err(anyhow!("Tf values must be in (0, 1], got {}", transmission))
+        }
+    };
+    Ok(Vector3D {
+        x: absorption(filter.x)?,
+        y: absorption(filter.y)?,
+        z: absorption(filter.z)?,
+    })
+}
+
+fn read_materials(
+    materials_path: &Path,
+    first_id: usize,
+    textures: &mut HashMap<String, Arc<Texture>>,
+) -> Result<Vec<Material>> {
     debug!("Reading materials from {}", materials_path.display());
     let file = File::open(materials_path)
         .unwrap_or_else(|_| panic!("Couldn't open materials file: {}", materials_path.display()));
     let reader = BufReader::new(file);
 
-    let mut materials: HashMap<String, Arc<Material>> = HashMap::new();
+    let mut materials = vec![];
     let mut current_material = Material::default();
     let mut current_material_started = false;
     for (n, line) in reader.lines().enumerate() {
@@ -66,11 +322,13 @@ fn read_materials(materials_path: &Path) -> Result<HashMap<String, Arc<Material>
             ["newmtl", name] => {
                 if current_material_started {
                     // Save previous material
-                    materials.insert(current_material.name.clone(), Arc::new(current_material));
+                    current_material.bsdf = current_material.build_bsdf();
+                    materials.push(current_material);
                 }
                 current_material = Material::default();
                 current_material_started = true;
                 current_material.name = (*name).to_owned();
+                current_material.id = first_id + materials.len();
                 anyhow::Ok(())
             }
             [key, body @ ..]
@@ -88,14 +346,148 @@ fn read_materials(materials_path: &Path) -> Result<HashMap<String, Arc<Material>
                 Ok(())
             }
             ["Ns", exponent] => {
-                current_material.specular_exponent = exponent.parse()?;
+                current_material.specular_exponent = Some(exponent.parse()?);
+                Ok(())
+            }
+            ["Pr", roughness] => {
+                current_material.roughness = Some(roughness.parse()?);
+                Ok(())
+            }
+            ["Pm", metallic] => {
+                current_material.metallic = metallic.parse()?;
                 Ok(())
             }
             ["Ni", index] => {
                 current_material.refraction_index = index.parse()?;
                 Ok(())
             }
-            ["illum", ..] => Ok(()),
+            [key @ ("map_Ka" | "map_Kd" | "map_Ks" | "map_Ns" | "map_Pr" | "map_Pm" | "map_d"), name] =>
+            {
+                let parameter = match *key {
+                    "map_Ka" => TexturedParameter::Ambient,
+                    "map_Kd" => TexturedParameter::Diffuse,
+                    "map_Ks" => TexturedParameter::Specular,
+                    "map_Ns" => TexturedParameter::SpecularExponent,
+                    "map_Pr" => TexturedParameter::Roughness,
+                    "map_Pm" => TexturedParameter::Metallic,
+                    "map_d" => TexturedParameter::Dissolve,
+                    _ => unreachable!(),
+                };
+                let texture = match textures.get(*name) {
+                    Some(texture) => Arc::clone(texture),
+                    None => {
+                        // Not a defined texture, so an image file
+                        let texture_path = materials_path.parent().unwrap().join(name);
+                        let open = match parameter {
+                            TexturedParameter::Ambient
+                            | TexturedParameter::Diffuse
+                            | TexturedParameter::Specular => ImageTexture::open_srgb,
+                            TexturedParameter::Dissolve => ImageTexture::open_alpha,
+                            _ => ImageTexture::open,
+                        };
+                        Arc::new(Texture {
+                            pattern: Pattern::Image(read_texture_with(open, &texture_path)?),
+                            colors: Default::default(),
+                            mapping: Mapping::Uv,
+                            transform: Default::default(),
+                        })
+                    }
+                };
+                current_material.textures.push((parameter, texture));
+                Ok(())
+            }
+            ["Texture", body @ ..] => {
+                let (name, texture) =
+                    read_texture_definition(body, materials_path.parent().unwrap())?;
+                textures.insert(name, Arc::new(texture));
+                Ok(())
+            }
+            ["norm", filename] => {
+                let texture_path = materials_path.parent().unwrap().join(filename);
+                current_material.normal_map = Some(Arc::new(read_texture(&texture_path)?));
+                Ok(())
+            }
+            ["bump" | "map_Bump", body @ ..] => {
+                let (texture, scale) = read_bump_map(body, materials_path.parent().unwrap())?;
+                current_material.bump_map = Some((Arc::new(texture), scale));
+                Ok(())
+            }
+            ["Ncauchy", body @ ..] => {
+                current_material.dispersion = Some(read_cauchy(body)?);
+                Ok(())
+            }
+            ["Nsellmeier", body @ ..] => {
+                current_material.dispersion = Some(read_sellmeier(body)?);
+                Ok(())
+            }
+            ["illum", model] => {
+                current_material.illum = Some(model.parse()?);
+                Ok(())
+            }
+            ["sides", sides] => {
+                current_material.one_sided = match *sides {
+                    "1" => true,
+                    "2" => false,
+                    _ => return Err(anyhow!("Number of sides must be 1 or 2")),
+                };
+                Ok(())
+            }
+            ["d", dissolve] => {
+                current_material.dissolve = dissolve.parse()?;
+                Ok(())
+            }
+            [key @ ("Ma" | "Ms"), body @ ..] => {
+                let triplet = read_point(body)?;
+                let medium = current_material
+                    .medium
+                    .get_or_insert_with(|| Medium::Homogeneous(Default::default()))
+                    .coefficients_mut();
+                match *key {
+                    "Ma" => medium.absorption = triplet,
+                    "Ms" => medium.scattering = triplet,
+                    _ => unreachable!(),
+                }
+                Ok(())
+            }
+            ["Tf", body @ ..] => {
+                let absorption = read_transmission_filter(body)?;
+                current_material
+                    .medium
+                    .get_or_insert_with(|| Medium::Homogeneous(Default::default()))
+                    .coefficients_mut()
+                    .absorption = absorption;
+                Ok(())
+            }
+            ["Mg", asymmetry] => {
+                let medium = current_material
+                    .medium
+                    .get_or_insert_with(|| Medium::Homogeneous(Default::default()))
+                    .coefficients_mut();
+                medium.phase.asymmetry = asymmetry.parse()?;
+                Ok(())
+            }
+            ["Mgrid", grid_filename] => {
+                let grid_path = materials_path.parent().unwrap().join(grid_filename);
+                match VoxelGrid::read(&grid_path) {
+                    Ok(grid) => {
+                        let coefficients = match current_material.medium.take() {
+                            None => Default::default(),
+                            Some(mut medium) => medium.coefficients_mut().clone(),
+                        };
+                        current_material.medium =
+                            Some(Medium::Grid(GridMedium::new(coefficients, grid)));
+                        Ok(())
+                    }
+                    Err(err) => Err(err.context(format!("reading {}", grid_path.display()))),
+                }
+            }
+            ["Mt", scale] => match current_material.medium {
+                Some(Medium::Grid(ref mut medium)) => {
+                    medium.emission_scale = scale.parse()?;
+                    Ok(())
+                }
+                _ => Err(anyhow!("Mt must follow Mgrid")),
+            },
             [smt, ..] if smt.starts_with('#') => Ok(()),
             _ => Err(anyhow!("Unknown .mtl key")),
         };
@@ -107,7 +499,8 @@ fn read_materials(materials_path: &Path) -> Result<HashMap<String, Arc<Material>
     }
     if current_material_started {
         // Save previous material
-        materials.insert(current_material.name.clone(), Arc::new(current_material));
+        current_material.bsdf = current_material.build_bsdf();
+        materials.push(current_material);
     }
 
     debug!("Done reading materials from {}", materials_path.display());
@@ -125,7 +518,7 @@ fn get_index(read_idx: isize, length: usize) -> usize {
 fn get_object_normal(
     vertices: &[Vector3D],
     normals: &[Vector3D],
-    idx: &[(isize, isize)],
+    idx: &[(isize, isize, isize)],
 ) -> Vector3D {
     let first_vertex = &vertices[get_index(idx[0].0, vertices.len())];
     let second_vertex = &vertices[get_index(idx[1].0, vertices.len())];
@@ -141,19 +534,20 @@ fn get_object_normal(
 }
 
 fn read_object(
-    body: &[&str],
+    indices: &[(isize, isize, isize)],
     vertices: &[Vector3D],
     read_normals: &[Vector3D],
-    assigned_normals: &mut [Vector3D],
-    material: &Arc<Material>,
-) -> Result<Vec<PseudoObject>> {
-    let indices_pairs = read_indices_pairs(body);
-    if indices_pairs.len() < 3 {
+    texture_coordinates: &[[f64; 2]],
+    attributes: &mut VertexAttributes,
+    material: usize,
+    object_id: usize,
+) -> Result<Vec<Triangle>> {
+    if indices.len() < 3 {
         return Err(anyhow!("Object can't have less than 3 vertices"));
     }
 
-    let no_normals = indices_pairs.iter().all(|(_, normal)| *normal == 0);
-    let all_normals = indices_pairs.iter().all(|(_, normal)| *normal != 0);
+    let no_normals = indices.iter().all(|(_, _, normal)| *normal == 0);
+    let all_normals = indices.iter().all(|(_, _, normal)| *normal != 0);
 
     if !no_normals && !all_normals {
         return Err(anyhow!(
@@ -161,22 +555,60 @@ fn read_object(
         ));
     }
 
-    let mut pseudo_objects = vec![];
+    let no_textures = indices.iter().all(|(_, texture, _)| *texture == 0);
+    let all_textures = indices.iter().all(|(_, texture, _)| *texture != 0);
 
-    let first_vertex_idx = get_index(indices_pairs[0].0, vertices.len());
-    for i in 1..indices_pairs.len() - 1 {
-        let second_vertex_idx = get_index(indices_pairs[i].0, vertices.len());
-        let third_vertex_idx = get_index(indices_pairs[i + 1].0, vertices.len());
-        pseudo_objects.push(PseudoObject {
-            material: Arc::clone(material),
-            first_vertex_idx,
-            second_vertex_idx,
-            third_vertex_idx,
+    if !no_textures && !all_textures {
+        return Err(anyhow!(
+            "Ether all vertices should have texture coordinates or none should"
+        ));
+    }
+
+    let mut triangles = vec![];
+
+    for i in 1..indices.len() - 1 {
+        let corners = [indices[0], indices[i], indices[i + 1]];
+        let vertex_indices = corners.map(|(vertex, _, _)| get_index(vertex, vertices.len()));
+        let texture_indices = all_textures
+            .then(|| corners.map(|(_, texture, _)| get_index(texture, texture_coordinates.len())));
+
+        let tangent_indices = texture_indices.map(|texture_indices| {
+            let points = vertex_indices.map(|i| &vertices[i]);
+            let derivatives =
+                position_derivatives(points, &texture_indices.map(|i| texture_coordinates[i]));
+            let face_normal = (points[1] - points[0]).cross(points[2] - points[0]);
+            let right_handed = derivatives
+                .as_ref()
+                .is_none_or(|(dpdu, dpdv)| &dpdu.cross(dpdv) * &face_normal >= 0.0);
+            let tangent_indices = [0, 1, 2].map(|corner| {
+                attributes.tangent_index((
+                    vertex_indices[corner],
+                    texture_indices[corner],
+                    right_handed,
+                ))
+            });
+            if let Some((dpdu, dpdv)) = derivatives {
+                for tangent_idx in tangent_indices {
+                    attributes.tangents[tangent_idx] += &dpdu / dpdu.len().max(1e-12);
+                    attributes.bitangents[tangent_idx] += &dpdv / dpdv.len().max(1e-12);
+                }
+            }
+            tangent_indices
+        });
+
+        triangles.push(Triangle {
+            vertices: vertex_indices.map(|i| i as u32),
+            texture_coordinates: texture_indices.map(|indices| indices.map(|i| i as u32)),
+            tangents: tangent_indices.map(|indices| indices.map(|i| i as u32)),
+            material: material as u32,
+            object_id: object_id as u32,
+            light: None,
         })
     }
 
-    let object_normal = get_object_normal(vertices, assigned_normals, indices_pairs.as_slice());
-    for (vertex_idx, normal_idx) in indices_pairs.iter() {
+    let assigned_normals = &mut attributes.normals;
+    let object_normal = get_object_normal(vertices, assigned_normals, indices);
+    for (vertex_idx, _, normal_idx) in indices.iter() {
         assigned_normals[get_index(*vertex_idx, vertices.len())] += if all_normals {
             read_normals[get_index(*normal_idx, read_normals.len())].clone()
         } else {
@@ -184,131 +616,555 @@ fn read_object(
         }
     }
 
-    Ok(pseudo_objects)
+    Ok(triangles)
 }
 
-fn read_light(body: &[&str]) -> Result<Light> {
-    if body.len() != 6 {
-        return Err(anyhow!("Light must have 6 points, got {}", body.len()));
+fn read_attenuation(body: &[&str]) -> Result<Attenuation> {
+    match body.first() {
+        None => Ok(Attenuation::default()),
+        Some(attenuation) => attenuation.parse(),
+    }
+}
+
+fn read_point_light(body: &[&str]) -> Result<PointLight> {
+    if body.len() != 6 && body.len() != 7 {
+        return Err(anyhow!(
+            "Point light must have 6 numbers and an optional attenuation, got {} tokens",
+            body.len()
+        ));
     }
 
-    let light = Light {
+    let light = PointLight {
         position: match read_point(&body[..3]) {
             Ok(position) => position,
             Err(err) => return Err(err.context("reading position")),
         },
-        intensity: match read_point(&body[..3]) {
+        intensity: match read_point(&body[3..6]) {
             Ok(intensity) => intensity,
             Err(err) => return Err(err.context("reading intensity")),
         },
+        attenuation: read_attenuation(&body[6..])?,
     };
 
     Ok(light)
 }
 
-pub fn read_scene(file_path: &Path) -> Result<Scene> {
-    info!("Reading scene from {}", file_path.display());
-    let file = File::open(file_path)
-        .unwrap_or_else(|_| panic!("Couldn't open scene file: {}", file_path.display()));
-    let reader = BufReader::new(file);
+fn read_directional_light(body: &[&str]) -> Result<DirectionalLight> {
+    if body.len() != 6 {
+        return Err(anyhow!(
+            "Directional light must have 6 numbers, got {}",
+            body.len()
+        ));
+    }
 
-    let mut vertices = vec![];
-    let mut read_normals = vec![];
-    let mut assigned_normals = vec![];
-    let mut materials = HashMap::new();
-    let mut pseudo_objects = vec![];
-    let mut lights = vec![];
-    let mut cube_map = None;
-    let mut current_material: Arc<Material> = Arc::new(Material::default());
+    let light = DirectionalLight {
+        direction: match read_point(&body[..3]) {
+            Ok(direction) => direction.normalize(),
+            Err(err) => return Err(err.context("reading direction")),
+        },
+        intensity: match read_point(&body[3..]) {
+            Ok(intensity) => intensity,
+            Err(err) => return Err(err.context("reading intensity")),
+        },
+    };
 
-    for (n, line) in reader.lines().enumerate() {
-        let line = line.unwrap();
+    Ok(light)
+}
 
-        let tokens: Vec<&str> = line.split_whitespace().collect();
-        if tokens.is_empty() {
-            continue;
+fn read_spot_light(body: &[&str], directory: &Path) -> Result<SpotLight> {
+    if !(11..=13).contains(&body.len()) {
+        return Err(anyhow!(
+            "Spot light must have 11 numbers, an optional attenuation and gobo, got {} tokens",
+            body.len()
+        ));
+    }
+
+    let position = match read_point(&body[..3]) {
+        Ok(position) => position,
+        Err(err) => return Err(err.context("reading position")),
+    };
+    let direction = match read_point(&body[3..6]) {
+        Ok(direction) => direction,
+        Err(err) => return Err(err.context("reading direction")),
+    };
+    let intensity = match read_point(&body[6..9]) {
+        Ok(intensity) => intensity,
+        Err(err) => return Err(err.context("reading intensity")),
+    };
+    let cone: f64 = body[9].parse()?;
+    if !(cone > 0.0 && cone < 90.0) {
+        return Err(anyhow!(
+            "Spot light cone must be between 0 and 90 degrees, got {}",
+            cone
+        ));
+    }
+    let falloff: f64 = body[10].parse()?;
+    // A single optional token is the attenuation if it reads as one, otherwise the gobo
+    let (attenuation, gobo_filename) = match body[11..] {
+        [] => (Attenuation::default(), None),
+        [optional] => match optional.parse() {
+            Ok(attenuation) => (attenuation, None),
+            Err(_) => (Attenuation::default(), Some(optional)),
+        },
+        [attenuation, gobo_filename] => (attenuation.parse()?, Some(gobo_filename)),
+        _ => unreachable!(),
+    };
+    let gobo_path = gobo_filename.map(|gobo_filename| directory.join(gobo_filename));
+
+    SpotLight::new(
+        position,
+        direction,
+        intensity,
+        cone,
+        falloff,
+        attenuation,
+        gobo_path.as_deref(),
+    )
+}
+
+fn read_atmosphere(body: &[&str]) -> Result<Medium> {
+    if body.len() != 6 && body.len() != 7 {
+        return Err(anyhow!(
+            "Atmosphere must have 6 numbers and an optional asymmetry, got {}",
+            body.len()
+        ));
+    }
+
+    Ok(Medium::Homogeneous(HomogeneousMedium {
+        absorption: match read_point(&body[..3]) {
+            Ok(absorption) => absorption,
+            Err(err) => return Err(err.context("reading absorption")),
+        },
+        scattering: match read_point(&body[3..6]) {
+            Ok(scattering) => scattering,
+            Err(err) => return Err(err.context("reading scattering")),
+        },
+        phase: HenyeyGreenstein {
+            asymmetry: body.get(6).map_or(Ok(0.0), |asymmetry| asymmetry.parse())?,
+        },
+    }))
+}
+
+fn read_physical_sky(body: &[&str]) -> Result<PhysicalSky> {
+    if body.len() != 7 {
+        return Err(anyhow!(
+            "Physical sky must have 7 numbers, got {}",
+            body.len()
+        ));
+    }
+
+    let sun_direction = match read_point(&body[..3]) {
+        Ok(sun_direction) => sun_direction,
+        Err(err) => return Err(err.context("reading sun direction")),
+    };
+    let turbidity: f64 = body[3].parse()?;
+    let ground_albedo = match read_point(&body[4..]) {
+        Ok(ground_albedo) => ground_albedo,
+        Err(err) => return Err(err.context("reading ground albedo")),
+    };
+
+    Ok(PhysicalSky::new(sun_direction, turbidity, ground_albedo))
+}
+
+/// Everything in the scene besides the geometry. It comes from the lines of the .obj
+/// that are read again when the geometry is taken from the scene cache.
+struct SceneParts {
+    /// Triangles refer to them by index, unnamed default first
+    materials: Vec<Material>,
+    /// Indices of the materials of the last .mtl by name
+    material_indices: HashMap<String, usize>,
+    textures: HashMap<String, Arc<Texture>>,
+    lights: Vec<Box<dyn Light>>,
+    sky: Option<Sky>,
+    atmosphere: Option<Medium>,
+    /// Read .mtl files, the cache goes stale when they change
+    material_files: Vec<PathBuf>,
+}
+
+impl SceneParts {
+    fn new() -> Self {
+        Self {
+            materials: vec![Material::default()],
+            material_indices: HashMap::new(),
+            textures: HashMap::new(),
+            lights: vec![],
+            sky: None,
+            atmosphere: None,
+            material_files: vec![],
         }
-        let line_parse_result = match tokens.as_slice() {
-            ["v", body @ ..] => match read_point(body) {
-                Ok(normal) => {
-                    vertices.push(normal);
-                    assigned_normals.push(Vector3D::default());
-                    anyhow::Ok(())
+    }
+
+    /// Reads a line of the .obj that doesn't describe geometry.
+    fn read_line(&mut self, tokens: &[&str], file_path: &Path) -> Result<()> {
+        match tokens {
+            ["mtllib", mtl_filename] => {
+                let mtl_path = file_path.parent().unwrap().join(Path::new(mtl_filename));
+                match read_materials(&mtl_path, self.materials.len(), &mut self.textures) {
+                    Ok(read_materials) => {
+                        self.material_indices = read_materials
+                            .iter()
+                            .enumerate()
+                            .map(|(index, material)| {
+                                (material.name.clone(), self.materials.len() + index)
+                            })
+                            .collect();
+                        self.materials.extend(read_materials);
+                        self.material_files.push(mtl_path);
+                        Ok(())
+                    }
+                    Err(err) => Err(err.context("reading underlying .mtl")),
                 }
-                Err(err) => Err(err.context("reading vertex")),
+            }
+            ["P", body @ ..] => match read_point_light(body) {
+                Ok(light) => {
+                    self.lights.push(Box::new(light));
+                    Ok(())
+                }
+                Err(err) => Err(err.context("reading light")),
             },
-            ["vt", ..] => Ok(()),
-            ["vn", body @ ..] => match read_point(body) {
-                Ok(normal) => {
-                    read_normals.push(normal);
+            ["DirectionalLight", body @ ..] => match read_directional_light(body) {
+                Ok(light) => {
+                    self.lights.push(Box::new(light));
                     Ok(())
                 }
-                Err(err) => Err(err.context("reading normal")),
+                Err(err) => Err(err.context("reading directional light")),
             },
-            ["f", body @ ..] => match read_object(
-                body,
-                vertices.as_slice(),
-                read_normals.as_slice(),
-                assigned_normals.as_mut_slice(),
-                &current_material,
+            ["SpotLight", body @ ..] => match read_spot_light(body, file_path.parent().unwrap()) {
+                Ok(light) => {
+                    self.lights.push(Box::new(light));
+                    Ok(())
+                }
+                Err(err) => Err(err.context("reading spot light")),
+            },
+            ["Sky", _, _, sky_filename] => match CubeMap::new(
+                CubeMapLayout::HorizontalCross,
+                &[&file_path.parent().unwrap().join(Path::new(sky_filename))],
             ) {
-                Ok(ref mut read_pseudo_objects) => {
-                    pseudo_objects.append(read_pseudo_objects);
+                Ok(cube_map) => {
+                    self.sky = Some(Sky::CubeMap(cube_map));
                     Ok(())
                 }
-                Err(err) => Err(err.context("reading object")),
+                Err(err) => Err(err.context("reading sky")),
             },
-            ["mtllib", mtl_filename] => {
-                match read_materials(&file_path.parent().unwrap().join(Path::new(mtl_filename))) {
-                    Ok(read_materials) => {
-                        materials = read_materials;
+            ["Sky", layout, sky_filenames @ ..] => match layout.parse::<CubeMapLayout>() {
+                Ok(layout) => {
+                    let expected_files = match layout {
+                        CubeMapLayout::Separate => 6,
+                        _ => 1,
+                    };
+                    if sky_filenames.len() == expected_files {
+                        let sky_paths: Vec<PathBuf> = sky_filenames
+                            .iter()
+                            .map(|sky_filename| file_path.parent().unwrap().join(sky_filename))
+                            .collect();
+                        match CubeMap::new(
+                            layout,
+                            &sky_paths
+                                .iter()
+                                .map(|path| path.as_path())
+                                .collect::<Vec<_>>(),
+                        ) {
+                            Ok(cube_map) => {
+                                self.sky = Some(Sky::CubeMap(cube_map));
+                                Ok(())
+                            }
+                            Err(err) => Err(err.context("reading sky")),
+                        }
+                    } else {
+                        Err(anyhow!(
+                            "Cube map layout needs {} files, got {}",
+                            expected_files,
+                            sky_filenames.len()
+                        ))
+                    }
+                }
+                Err(err) => Err(err.context("reading sky")),
+            },
+            ["Environment", map_filename, options @ ..] if options.len() <= 2 => {
+                let rotation = options.first().map_or(Ok(0.0), |value| value.parse());
+                let intensity = options.get(1).map_or(Ok(1.0), |value| value.parse());
+                match (rotation, intensity) {
+                    (Ok(rotation), Ok(intensity)) => match EnvironmentMap::new(
+                        &file_path.parent().unwrap().join(Path::new(map_filename)),
+                        rotation,
+                        intensity,
+                    ) {
+                        Ok(environment_map) => {
+                            self.sky = Some(Sky::EnvironmentMap(environment_map));
+                            Ok(())
+                        }
+                        Err(err) => Err(err.context("reading environment map")),
+                    },
+                    _ => Err(anyhow!(
+                        "Environment rotation and intensity must be numbers"
+                    )),
+                }
+            }
+            ["Atmosphere", body @ ..] => match read_atmosphere(body) {
+                Ok(medium) => {
+                    self.atmosphere = Some(medium);
+                    Ok(())
+                }
+                Err(err) => Err(err.context("reading atmosphere")),
+            },
+            ["Texture", body @ ..] => {
+                match read_texture_definition(body, file_path.parent().unwrap()) {
+                    Ok((name, texture)) => {
+                        self.textures.insert(name, Arc::new(texture));
                         Ok(())
                     }
-                    Err(err) => Err(err.context("reading underlying .mtl")),
+                    Err(err) => Err(err.context("reading texture")),
                 }
             }
-            ["usemtl", mtl_name] => {
-                current_material = Arc::clone(&materials[*mtl_name]);
-                Ok(())
+            ["PhysicalSky", body @ ..] => match read_physical_sky(body) {
+                Ok(physical_sky) => {
+                    self.sky = Some(Sky::Physical(physical_sky));
+                    Ok(())
+                }
+                Err(err) => Err(err.context("reading physical sky")),
+            },
+            _ => Err(anyhow!("Unknown .obj key")),
+        }
+    }
+
+    /// Scene of the `mesh` with these materials, adding area lights for emissive triangles.
+    fn into_scene(self, mut mesh: Mesh, bvh: Option<Bvh>) -> Scene {
+        let mut lights = self.lights;
+        for triangle in mesh.triangles.iter_mut() {
+            let material = &self.materials[triangle.material as usize];
+            triangle.light = None;
+            if !material.is_emissive() {
+                continue;
             }
-            ["P", body @ ..] => match read_light(body) {
-                Ok(light) => {
-                    lights.push(light);
+            let points = triangle
+                .vertices
+                .map(|vertex| mesh.positions[vertex as usize].cast());
+            if let Some(light) = AreaLight::new(
+                points.each_ref(),
+                material.intensity.clone(),
+                !material.one_sided,
+            ) {
+                triangle.light = Some(lights.len() as u32);
+                lights.push(Box::new(light));
+            }
+        }
+        mesh.materials = self.materials;
+        let light_bvh = LightBvh::new(&lights);
+        let bvh = bvh.unwrap_or_else(|| Bvh::new(&mesh));
+
+        Scene {
+            mesh,
+            bvh,
+            lights,
+            light_bvh,
+            sky: self.sky,
+            atmosphere: self.atmosphere,
+        }
+    }
+}
+
+/// Reads the scene, taking the geometry from the `cache` when it is up to date
+/// and writing it there otherwise.
+pub fn read_scene(file_path: &Path, cache: Option<&SceneCache>) -> Result<Scene> {
+    if let Some(cached) = cache.and_then(|cache| cache.load(file_path)) {
+        let mut parts = SceneParts::new();
+        for (n, line) in &cached.directives {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if let Err(err) = parts.read_line(&tokens, file_path) {
+                return Err(add_line_context(err, file_path, n + 1));
+            }
+        }
+        let material_files = parts.material_files.clone();
+        let scene = parts.into_scene(cached.mesh, cached.bvh);
+        if let Some(cache) = cache.filter(|_| cached.needs_rewrite) {
+            cache.store(
+                file_path,
+                &material_files,
+                &cached.directives,
+                &scene.mesh,
+                &scene.bvh,
+            );
+        }
+        return Ok(scene);
+    }
+
+    info!("Reading scene from {}", file_path.display());
+    let file = File::open(file_path)
+        .unwrap_or_else(|_| panic!("Couldn't open scene file: {}", file_path.display()));
+    // SAFETY: the map is only read, and dropped once the file is parsed. Nothing in this process
+    // writes to the scene file, even a scene cache at the same path replaces it by renaming,
+    // which leaves the mapped file alone. Other processes must not change the file meanwhile,
+    // which is a documented requirement of the renderer (see the README).
+    let map = unsafe { Mmap::map(&file) }
+        .unwrap_or_else(|_| panic!("Couldn't map scene file: {}", file_path.display()));
+
+    // Vertex data is read by chunks in parallel, everything else in order afterwards
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunks = thread::scope(|scope| {
+        let handles: Vec<_> = chunk::split(&map, threads)
+            .into_iter()
+            .map(|bytes| scope.spawn(|| Chunk::read(bytes)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    let Chunk {
+        vertices,
+        texture_coordinates,
+        normals: read_normals,
+        face_indices,
+        statements,
+        error,
+        ..
+    } = Chunk::merge(chunks);
+
+    let mut attributes = VertexAttributes::new(vertices.len());
+    let mut parts = SceneParts::new();
+    // Lines read by `parts`, kept for the cache
+    let mut directives = vec![];
+    let mut groups: HashMap<String, usize> = HashMap::new();
+    let mut current_object_id = 0;
+    let mut triangles = vec![];
+    let mut current_material = 0;
+
+    for (n, statement) in statements {
+        let line_parse_result = match statement {
+            Statement::Face { counts, indices } => match read_object(
+                &face_indices[indices],
+                &vertices[..counts.vertices],
+                &read_normals[..counts.normals],
+                &texture_coordinates[..counts.texture_coordinates],
+                &mut attributes,
+                current_material,
+                current_object_id,
+            ) {
+                Ok(ref mut read_triangles) => {
+                    triangles.append(read_triangles);
                     Ok(())
                 }
-                Err(err) => Err(err.context("reading light")),
+                Err(err) => Err(err.context("reading object")),
             },
-            ["Sky", _, _, sky_filename] => {
-                cube_map = Some(CubeMap::new(
-                    &file_path.parent().unwrap().join(Path::new(sky_filename)),
-                ));
-                Ok(())
+            Statement::Other(line) => {
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match tokens.as_slice() {
+                    ["usemtl", mtl_name] => {
+                        current_material = parts.material_indices[*mtl_name];
+                        Ok(())
+                    }
+                    ["g" | "o", names @ ..] => {
+                        let next_object_id = groups.len() + 1;
+                        current_object_id =
+                            *groups.entry(names.join(" ")).or_insert(next_object_id);
+                        Ok(())
+                    }
+                    ["s", ..] => Ok(()),
+                    _ => {
+                        let result = parts.read_line(&tokens, file_path);
+                        if result.is_ok() {
+                            directives.push((n, line.to_string()));
+                        }
+                        result
+                    }
+                }
             }
-            [smt, ..] if smt.starts_with('#') => Ok(()),
-            ["g", ..] => Ok(()),
-            ["s", ..] => Ok(()),
-            _ => Err(anyhow!("Unknown .obj key")),
         };
 
-        match line_parse_result {
-            Ok(()) => {}
-            Err(err) => return Err(add_line_context(err, file_path, n + 1)),
+        if let Err(err) = line_parse_result {
+            return Err(add_line_context(err, file_path, n + 1));
         }
     }
-
-    let final_normals: Vec<Vector3D> = assigned_normals
-        .iter_mut()
-        .map(|normal| normal.clone().normalize())
-        .collect();
+    if let Some((n, err)) = error {
+        return Err(add_line_context(err, file_path, n + 1));
+    }
+    drop(map);
 
     info!("Done reading scene from {}", file_path.display());
 
-    Ok(Scene {
-        objects: pseudo_objects
+    let mesh = Mesh {
+        normals: attributes
+            .normals
             .iter()
-            .map(|x| x.build_object(&vertices, &final_normals))
+            .map(|normal| normal.clone().normalize().cast())
             .collect(),
-        lights,
-        cube_map,
-    })
+        tangents: attributes.tangents.iter().map(Vector3D::cast).collect(),
+        bitangents: attributes.bitangents.iter().map(Vector3D::cast).collect(),
+        positions: vertices.iter().map(Vector3D::cast).collect(),
+        texture_coordinates,
+        materials: vec![],
+        triangles,
+    };
+    let material_files = parts.material_files.clone();
+    let scene = parts.into_scene(mesh, None);
+    if let Some(cache) = cache {
+        cache.store(
+            file_path,
+            &material_files,
+            &directives,
+            &scene.mesh,
+            &scene.bvh,
+        );
+    }
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn bump_map_options_can_come_in_any_order() {
+        let directory = std::env::temp_dir();
+        let filename = format!("bump_{}.png", std::process::id());
+        RgbImage::from_pixel(1, 1, Rgb([255, 255, 255]))
+            .save(directory.join(&filename))
+            .unwrap();
+
+        let read = |body: &[&str]| read_bump_map(body, &directory).map(|(_, scale)| scale);
+        assert_eq!(read(&[&filename]).unwrap(), 1.0);
+        assert_eq!(read(&["-bm", "0.5", &filename]).unwrap(), 0.5);
+        assert_eq!(read(&[&filename, "-bm", "0.25"]).unwrap(), 0.25);
+        assert!(read(&["-bm", &filename]).is_err());
+        assert!(read(&[&filename, "-bm"]).is_err());
+        assert!(read(&["-clamp", "on", &filename]).is_err());
+        assert!(read(&[&filename, &filename]).is_err());
+
+        std::fs::remove_file(directory.join(&filename)).unwrap();
+    }
+
+    /// Error of reading a scene that fails, with all of its context.
+    fn read_error(obj: &str) -> String {
+        let path = std::env::temp_dir().join(format!("read_error_{}.obj", std::process::id()));
+        std::fs::write(&path, obj).unwrap();
+        let result = read_scene(&path, None);
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Ok(_) => panic!("{:?} was read", obj),
+            Err(err) => format!("{:#}", err),
+        }
+    }
+
+    #[test]
+    fn missing_environment_maps_are_errors_on_their_line() {
+        let error = read_error("v 0 0 0\nEnvironment missing.hdr\n");
+        assert!(error.contains("on line 2"), "{}", error);
+        assert!(error.contains("reading environment map"), "{}", error);
+        assert!(error.contains("missing.hdr"), "{}", error);
+    }
+
+    #[test]
+    fn missing_gobos_are_errors_on_their_line() {
+        let error = read_error("v 0 0 0\nSpotLight 0 2 0 0 -1 0 1 1 1 30 20 missing.png\n");
+        assert!(error.contains("on line 2"), "{}", error);
+        assert!(error.contains("reading spot light"), "{}", error);
+        assert!(error.contains("missing.png"), "{}", error);
+    }
 }
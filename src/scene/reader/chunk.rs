@@ -0,0 +1,274 @@
+use std::ops::{Add, Range};
+
+use anyhow::Error;
+
+use super::{read_face_indices, read_point, read_texture_coordinates};
+use crate::geometry::vector::Vector3D;
+
+/// Smaller parts of the .obj aren't worth a thread.
+const MIN_CHUNK_SIZE: usize = 1 << 20;
+
+/// Numbers of vertex data items read before a line.
+#[derive(Clone, Copy, Default)]
+pub struct Counts {
+    pub vertices: usize,
+    pub texture_coordinates: usize,
+    pub normals: usize,
+}
+
+impl Add for Counts {
+    type Output = Counts;
+
+    fn add(self, other: Counts) -> Counts {
+        Counts {
+            vertices: self.vertices + other.vertices,
+            texture_coordinates: self.texture_coordinates + other.texture_coordinates,
+            normals: self.normals + other.normals,
+        }
+    }
+}
+
+/// Line that has to be read in order, after the vertex data of all chunks.
+pub enum Statement<'a> {
+    /// Face with its range of the face indices, which can refer back to the vertex data
+    /// read before it
+    Face {
+        counts: Counts,
+        indices: Range<usize>,
+    },
+    Other(&'a str),
+}
+
+/// Part of the .obj starting at a line start. Vertex data is read right away,
+/// so that chunks can be read in parallel.
+#[derive(Default)]
+pub struct Chunk<'a> {
+    pub vertices: Vec<Vector3D>,
+    pub texture_coordinates: Vec<[f64; 2]>,
+    pub normals: Vec<Vector3D>,
+    pub face_indices: Vec<(isize, isize, isize)>,
+    /// Statements with their line indices
+    pub statements: Vec<(usize, Statement<'a>)>,
+    /// Index of the first line that couldn't be read and why, reading stops there
+    pub error: Option<(usize, Error)>,
+    lines: usize,
+}
+
+/// Splits the file at line ends into up to `parts` chunks.
+pub fn split(bytes: &[u8], parts: usize) -> Vec<&[u8]> {
+    split_by_size(bytes, (bytes.len() / parts.max(1)).max(MIN_CHUNK_SIZE))
+}
+
+/// Splits the file into chunks of at least `size` bytes, up to the end of the line
+/// the size falls into.
+fn split_by_size(bytes: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let end = match rest.get(size..) {
+            Some(tail) => tail
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(rest.len(), |line_end| size + line_end + 1),
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+impl<'a> Chunk<'a> {
+    pub fn read(bytes: &'a [u8]) -> Self {
+        let text = std::str::from_utf8(bytes).expect("Scene file must be UTF-8");
+        let mut chunk = Chunk {
+            lines: bytes.iter().filter(|&&byte| byte == b'\n').count(),
+            ..Chunk::default()
+        };
+
+        let mut body = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let Some(key) = tokens.next() else {
+                continue;
+            };
+            body.clear();
+            body.extend(tokens);
+            let line_parse_result = match key {
+                "v" => match read_point(&body) {
+                    Ok(vertex) => {
+                        chunk.vertices.push(vertex);
+                        Ok(())
+                    }
+                    Err(err) => Err(err.context("reading vertex")),
+                },
+                "vt" => match read_texture_coordinates(&body) {
+                    Ok(coordinates) => {
+                        chunk.texture_coordinates.push(coordinates);
+                        Ok(())
+                    }
+                    Err(err) => Err(err.context("reading texture coordinates")),
+                },
+                "vn" => match read_point(&body) {
+                    Ok(normal) => {
+                        chunk.normals.push(normal);
+                        Ok(())
+                    }
+                    Err(err) => Err(err.context("reading normal")),
+                },
+                "f" => {
+                    let first = chunk.face_indices.len();
+                    read_face_indices(&body, &mut chunk.face_indices);
+                    let statement = Statement::Face {
+                        counts: chunk.counts(),
+                        indices: first..chunk.face_indices.len(),
+                    };
+                    chunk.statements.push((n, statement));
+                    Ok(())
+                }
+                _ if key.starts_with('#') => Ok(()),
+                _ => {
+                    chunk.statements.push((n, Statement::Other(line)));
+                    Ok(())
+                }
+            };
+
+            if let Err(err) = line_parse_result {
+                chunk.error = Some((n, err));
+                break;
+            }
+        }
+
+        chunk
+    }
+
+    /// Chunk of the whole file, with line numbers, vertex data counts and face index ranges
+    /// shifted by the chunks before. Stops at the first chunk with an error.
+    pub fn merge(chunks: Vec<Chunk<'a>>) -> Self {
+        let mut merged = Chunk::default();
+        for mut chunk in chunks {
+            let (lines, counts, faces) = (merged.lines, merged.counts(), merged.face_indices.len());
+            merged
+                .statements
+                .extend(chunk.statements.into_iter().map(|(n, statement)| {
+                    let statement = match statement {
+                        Statement::Face {
+                            counts: face_counts,
+                            indices,
+                        } => Statement::Face {
+                            counts: counts + face_counts,
+                            indices: faces + indices.start..faces + indices.end,
+                        },
+                        other => other,
+                    };
+                    (lines + n, statement)
+                }));
+            merged.vertices.append(&mut chunk.vertices);
+            merged
+                .texture_coordinates
+                .append(&mut chunk.texture_coordinates);
+            merged.normals.append(&mut chunk.normals);
+            merged.face_indices.append(&mut chunk.face_indices);
+            merged.lines += chunk.lines;
+
+            if let Some((n, err)) = chunk.error {
+                merged.error = Some((lines + n, err));
+                break;
+            }
+        }
+
+        merged
+    }
+
+    fn counts(&self) -> Counts {
+        Counts {
+            vertices: self.vertices.len(),
+            texture_coordinates: self.texture_coordinates.len(),
+            normals: self.normals.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SCENE: &str = "# comment\n\
+        mtllib materials.mtl\n\
+        v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+        vt 0 0\nvt 1 0\r\nvt 0 1\n\
+        vn 0 0 1\n\
+        \n\
+        usemtl white\n\
+        f 1/1/1 2/2/1 3/3/1\n\
+        f -3/-3/-1 -2/-2/-1 -1/-1/-1\n\
+        v 2 2 0\r\n\
+        g back\n\
+        f -1 -2 -3 -4\n\
+        v 3 3 0\n\
+        f 5 -1/3 -3/1\n";
+
+    /// Lines of the chunk as they would be read, with face indices resolved to the
+    /// vertex data, so that negative indices show what they refer to.
+    fn summary(chunk: &Chunk) -> Vec<String> {
+        let resolve = |index: isize, count: usize| {
+            if index < 0 {
+                count.wrapping_add_signed(index)
+            } else {
+                (index as usize).wrapping_sub(1)
+            }
+        };
+        let mut lines: Vec<String> = chunk
+            .statements
+            .iter()
+            .map(|(n, statement)| match statement {
+                Statement::Other(line) => format!("{} {}", n, line),
+                Statement::Face { counts, indices } => {
+                    let corners: Vec<String> = chunk.face_indices[indices.clone()]
+                        .iter()
+                        .map(|&(vertex, texture, normal)| {
+                            let vertex = &chunk.vertices[resolve(vertex, counts.vertices)];
+                            format!(
+                                "{:?}/{:?}/{:?}",
+                                [vertex[0], vertex[1], vertex[2]],
+                                chunk
+                                    .texture_coordinates
+                                    .get(resolve(texture, counts.texture_coordinates)),
+                                chunk
+                                    .normals
+                                    .get(resolve(normal, counts.normals))
+                                    .map(|normal| [normal[0], normal[1], normal[2]]),
+                            )
+                        })
+                        .collect();
+                    format!("{} f {}", n, corners.join(" "))
+                }
+            })
+            .collect();
+        lines.push(format!(
+            "error at {:?}",
+            chunk.error.as_ref().map(|(n, _)| n)
+        ));
+        lines
+    }
+
+    #[test]
+    fn chunks_read_like_the_whole_file() {
+        let broken = format!("{}v 1 x 2\nv 4 4 0\nf -1 -2 -3\n", SCENE);
+        for text in [SCENE, &broken] {
+            let bytes = text.as_bytes();
+            let sequential = summary(&Chunk::read(bytes));
+            // Split points in the middle of every line
+            for size in 1..=bytes.len() {
+                let chunks = split_by_size(bytes, size);
+                assert_eq!(chunks.concat(), bytes);
+                assert!(chunks[..chunks.len() - 1]
+                    .iter()
+                    .all(|chunk| chunk.ends_with(b"\n")));
+                let merged = Chunk::merge(chunks.into_iter().map(Chunk::read).collect());
+                assert_eq!(summary(&merged), sequential, "chunks of {} bytes", size);
+            }
+        }
+    }
+}
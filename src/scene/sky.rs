@@ -0,0 +1,65 @@
+use rand::rngs::ThreadRng;
+
+use crate::geometry::ray::Ray;
+use crate::geometry::vector::Vector3D;
+use crate::scene::cube_map::CubeMap;
+use crate::scene::environment_map::EnvironmentMap;
+use crate::scene::physical_sky::PhysicalSky;
+
+pub struct SkySample {
+    pub direction: Vector3D,
+    pub radiance: Vector3D,
+    pub pdf: f64,
+}
+
+/// Radiance coming from infinitely far away, seen by rays that escape the scene.
+pub enum Sky {
+    CubeMap(CubeMap),
+    EnvironmentMap(EnvironmentMap),
+    Physical(PhysicalSky),
+}
+
+impl Sky {
+    pub fn trace(&self, ray: &Ray) -> Vector3D {
+        match self {
+            Sky::CubeMap(cube_map) => cube_map.trace(ray),
+            Sky::EnvironmentMap(environment_map) => environment_map.trace(ray),
+            Sky::Physical(physical_sky) => physical_sky.trace(ray),
+        }
+    }
+
+    /// Radiance that isn't already gathered by sampling the sky as a light source with `sample`.
+    pub fn trace_unsampled(&self, ray: &Ray) -> Vector3D {
+        match self {
+            Sky::CubeMap(cube_map) => cube_map.trace(ray),
+            Sky::EnvironmentMap(_) => Vector3D::default(),
+            Sky::Physical(physical_sky) => physical_sky.trace_without_sun(ray),
+        }
+    }
+
+    /// Radiance that is gathered by `sample`, `trace` is the sum of this and `trace_unsampled`.
+    pub fn trace_sampled(&self, ray: &Ray) -> Vector3D {
+        match self {
+            Sky::CubeMap(_) => Vector3D::default(),
+            Sky::EnvironmentMap(environment_map) => environment_map.trace(ray),
+            Sky::Physical(physical_sky) => physical_sky.trace_sun(ray),
+        }
+    }
+
+    pub fn sample(&self, rng: &mut ThreadRng) -> Option<SkySample> {
+        match self {
+            Sky::CubeMap(_) => None,
+            Sky::EnvironmentMap(environment_map) => environment_map.sample(rng),
+            Sky::Physical(physical_sky) => physical_sky.sample_sun(rng),
+        }
+    }
+
+    /// Density of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Vector3D) -> f64 {
+        match self {
+            Sky::CubeMap(_) => 0.0,
+            Sky::EnvironmentMap(environment_map) => environment_map.pdf(direction),
+            Sky::Physical(physical_sky) => physical_sky.sun_pdf(direction),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::OnceLock;
+
+use crate::geometry::vector::Vector3D;
+use crate::scene::distribution::Distribution1D;
+
+/// Visible range used for integrating spectra, nanometers
+static MIN_WAVELENGTH: f64 = 360.0;
+static MAX_WAVELENGTH: f64 = 830.0;
+static WAVELENGTH_STEP: f64 = 5.0;
+
+/// Edges of the smooth steps between the blue, green and red parts of the spectrum, nanometers
+static BLUE_GREEN_EDGE: (f64, f64) = (480.0, 510.0);
+static GREEN_RED_EDGE: (f64, f64) = (575.0, 605.0);
+
+/// Temperature of the blackbody with unit luminance, kelvins
+static REFERENCE_TEMPERATURE: f64 = 1000.0;
+
+fn piecewise_gaussian(wavelength: f64, mean: f64, left_sigma: f64, right_sigma: f64) -> f64 {
+    let sigma = if wavelength < mean {
+        left_sigma
+    } else {
+        right_sigma
+    };
+    let t = (wavelength - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 color matching functions at a wavelength in nanometers,
+/// multi-lobe fit by Wyman et al. 2013.
+pub fn cie_xyz(wavelength: f64) -> Vector3D {
+    Vector3D {
+        x: 1.056 * piecewise_gaussian(wavelength, 599.8, 37.9, 31.0)
+            + 0.362 * piecewise_gaussian(wavelength, 442.0, 16.0, 26.7)
+            - 0.065 * piecewise_gaussian(wavelength, 501.1, 20.4, 26.2),
+        y: 0.821 * piecewise_gaussian(wavelength, 568.8, 46.9, 40.5)
+            + 0.286 * piecewise_gaussian(wavelength, 530.9, 16.3, 31.1),
+        z: 1.217 * piecewise_gaussian(wavelength, 437.0, 11.8, 36.0)
+            + 0.681 * piecewise_gaussian(wavelength, 459.0, 26.0, 13.8),
+    }
+}
+
+/// CIE XYZ to linear sRGB (D65).
+pub fn xyz_to_rgb(xyz: &Vector3D) -> Vector3D {
+    Vector3D {
+        x: 3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        y: -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        z: 0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    }
+}
+
+/// Planck's law, spectral radiance at a wavelength in nanometers, up to a constant factor.
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+    // Second radiation constant hc/k, nm·K
+    static C2: f64 = 1.438_777e7;
+    1.0 / (wavelength.powi(5) * ((C2 / (wavelength * temperature)).exp() - 1.0))
+}
+
+fn wavelengths() -> impl Iterator<Item = f64> {
+    let steps = ((MAX_WAVELENGTH - MIN_WAVELENGTH) / WAVELENGTH_STEP) as usize;
+    (0..=steps).map(|i| MIN_WAVELENGTH + i as f64 * WAVELENGTH_STEP)
+}
+
+/// CIE XYZ of a spectrum given by its values at wavelengths in nanometers.
+fn spectrum_to_xyz(spectrum: impl Fn(f64) -> f64) -> Vector3D {
+    wavelengths().fold(Vector3D::default(), |xyz, wavelength| {
+        xyz + cie_xyz(wavelength) * (spectrum(wavelength) * WAVELENGTH_STEP)
+    })
+}
+
+fn blackbody_xyz(temperature: f64) -> Vector3D {
+    spectrum_to_xyz(|wavelength| planck(wavelength, temperature))
+}
+
+/// Linear sRGB radiance of a blackbody at `temperature` kelvins, relative to the luminance
+/// of a 1000 K blackbody. Colors outside of sRGB are clamped.
+pub fn blackbody(temperature: f64) -> Vector3D {
+    if temperature <= 0.0 {
+        return Vector3D::default();
+    }
+    let rgb = xyz_to_rgb(&blackbody_xyz(temperature)) / blackbody_xyz(REFERENCE_TEMPERATURE).y;
+    Vector3D {
+        x: rgb.x.max(0.0),
+        y: rgb.y.max(0.0),
+        z: rgb.z.max(0.0),
+    }
+}
+
+/// Linear sRGB of CIE XYZ, white balanced so that the constant unit spectrum is white.
+pub fn xyz_to_white_balanced_rgb(xyz: &Vector3D) -> Vector3D {
+    static WHITE: OnceLock<Vector3D> = OnceLock::new();
+    let white = WHITE.get_or_init(|| xyz_to_rgb(&spectrum_to_xyz(|_| 1.0)));
+    let rgb = xyz_to_rgb(xyz);
+    Vector3D {
+        x: rgb.x / white.x,
+        y: rgb.y / white.y,
+        z: rgb.z / white.z,
+    }
+}
+
+fn smoothstep((from, to): (f64, f64), x: f64) -> f64 {
+    let t = ((x - from) / (to - from)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Smooth red, green and blue parts of the spectrum, they sum up to one at every wavelength.
+fn basis(wavelength: f64) -> Vector3D {
+    let red = smoothstep(GREEN_RED_EDGE, wavelength);
+    let blue = 1.0 - smoothstep(BLUE_GREEN_EDGE, wavelength);
+    Vector3D {
+        x: red,
+        y: 1.0 - red - blue,
+        z: blue,
+    }
+}
+
+/// Rows of the inverse of the matrix with the white balanced colors of the basis spectra
+/// as columns, so that upsampled colors turn back into themselves.
+fn basis_correction() -> &'static [Vector3D; 3] {
+    static CORRECTION: OnceLock<[Vector3D; 3]> = OnceLock::new();
+    CORRECTION.get_or_init(|| {
+        let columns = [0, 1, 2].map(|i| {
+            xyz_to_white_balanced_rgb(&spectrum_to_xyz(|wavelength| basis(wavelength)[i]))
+        });
+        let determinant = &columns[0] * &columns[1].cross(&columns[2]);
+        // Rows of the inverse are the cross products of the columns
+        [
+            columns[1].cross(&columns[2]) / determinant,
+            columns[2].cross(&columns[0]) / determinant,
+            columns[0].cross(&columns[1]) / determinant,
+        ]
+    })
+}
+
+/// Value at a wavelength in nanometers of a smooth spectrum with the given linear sRGB color.
+/// Upsampling is linear, so sums and scaled colors keep their meaning, and white is
+/// the constant spectrum.
+pub fn rgb_to_spectrum(rgb: &Vector3D, wavelength: f64) -> f64 {
+    let correction = basis_correction();
+    let weights = Vector3D {
+        x: &correction[0] * rgb,
+        y: &correction[1] * rgb,
+        z: &correction[2] * rgb,
+    };
+    &weights * &basis(wavelength)
+}
+
+/// Picks a wavelength in nanometers proportionally to the sum of the color matching functions,
+/// returns it with its density.
+pub fn sample_wavelength(u: f64) -> (f64, f64) {
+    static DISTRIBUTION: OnceLock<Distribution1D> = OnceLock::new();
+    let distribution = DISTRIBUTION.get_or_init(|| {
+        let bins = ((MAX_WAVELENGTH - MIN_WAVELENGTH) / WAVELENGTH_STEP) as usize;
+        Distribution1D::new(
+            (0..bins)
+                .map(|i| {
+                    let xyz = cie_xyz(MIN_WAVELENGTH + (i as f64 + 0.5) * WAVELENGTH_STEP);
+                    xyz.x.abs() + xyz.y + xyz.z
+                })
+                .collect(),
+        )
+    });
+    let (x, pdf, _) = distribution.sample(u);
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    (MIN_WAVELENGTH + x * range, pdf / range)
+}
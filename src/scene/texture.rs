@@ -0,0 +1,348 @@
+use std::path::Path;
+
+use anyhow::Result;
+use image::ColorType;
+
+use crate::geometry::vector::Vector3D;
+use noise::{fbm, turbulence};
+
+pub mod noise;
+
+/// One resolution of an image, repeating outside of its bounds.
+struct MipLevel {
+    width: usize,
+    height: usize,
+    data: Vec<Vector3D>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: isize, y: isize) -> &Vector3D {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        &self.data[x + self.width * y]
+    }
+
+    /// Texture coordinates have v going up, image rows go down.
+    fn bilinear(&self, [u, v]: [f64; 2]) -> Vector3D {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        self.texel(x0, y0) * ((1.0 - dx) * (1.0 - dy))
+            + self.texel(x0 + 1, y0) * (dx * (1.0 - dy))
+            + self.texel(x0, y0 + 1) * ((1.0 - dx) * dy)
+            + self.texel(x0 + 1, y0 + 1) * (dx * dy)
+    }
+
+    /// Half the resolution, rounded up, averaging 2x2 blocks of texels.
+    fn downsampled(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                data.push(
+                    (self.texel(2 * x, 2 * y)
+                        + self.texel(2 * x + 1, 2 * y)
+                        + self.texel(2 * x, 2 * y + 1)
+                        + self.texel(2 * x + 1, 2 * y + 1))
+                        / 4.0,
+                );
+            }
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// Linear value of an sRGB encoded one.
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Image looked up by texture coordinates, repeating outside of [0, 1], with a mip pyramid
+/// for filtering over a footprint.
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    /// Full resolution first, down to a single texel
+    levels: Vec<MipLevel>,
+}
+
+impl ImageTexture {
+    /// Values as they are stored, which suits normal, height and other non-color maps.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::read(path, false)
+    }
+
+    /// Colors decoded from sRGB, except for floating point images, which are linear already.
+    pub fn open_srgb(path: &Path) -> Result<Self> {
+        Self::read(path, true)
+    }
+
+    fn read(path: &Path, srgb: bool) -> Result<Self> {
+        let image = image::open(path)?;
+        let decode = srgb && !matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let image = image.into_rgb32f();
+        let channel = |value: f32| {
+            if decode {
+                srgb_to_linear(value as f64)
+            } else {
+                value as f64
+            }
+        };
+        Ok(Self::from_level(MipLevel {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            data: image
+                .pixels()
+                .map(|pixel| Vector3D {
+                    x: channel(pixel.0[0]),
+                    y: channel(pixel.0[1]),
+                    z: channel(pixel.0[2]),
+                })
+                .collect(),
+        }))
+    }
+
+    /// Alpha channel of the image in all channels, images without one are read as they are.
+    pub fn open_alpha(path: &Path) -> Result<Self> {
+        let image = image::open(path)?;
+        if !image.color().has_alpha() {
+            return Self::open(path);
+        }
+        let image = image.into_rgba32f();
+        Ok(Self::from_level(MipLevel {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            data: image
+                .pixels()
+                .map(|pixel| Vector3D::from([pixel.0[3] as f64; 3]))
+                .collect(),
+        }))
+    }
+
+    fn from_level(base: MipLevel) -> Self {
+        let (width, height) = (base.width, base.height);
+        let mut levels = vec![base];
+        while let Some(level) = levels.last().filter(|level| level.width * level.height > 1) {
+            levels.push(level.downsampled());
+        }
+        Self {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    /// Bilinear lookup at full resolution.
+    pub fn lookup(&self, coordinates: [f64; 2]) -> Vector3D {
+        self.levels[0].bilinear(coordinates)
+    }
+
+    /// Filtered lookup over the footprint given by `differentials` if they are known,
+    /// full resolution otherwise.
+    pub fn lookup_at(
+        &self,
+        coordinates: [f64; 2],
+        differentials: Option<&SurfaceDifferentials>,
+    ) -> Vector3D {
+        match differentials {
+            Some(differentials) => self.filtered(coordinates, differentials.coordinates),
+            None => self.lookup(coordinates),
+        }
+    }
+
+    /// Trilinear lookup over a footprint given by the derivatives of the coordinates
+    /// along the pixel x and y.
+    pub fn filtered(&self, coordinates: [f64; 2], derivatives: [[f64; 2]; 2]) -> Vector3D {
+        let footprint = derivatives
+            .map(|[du, dv]| (du * self.width as f64).hypot(dv * self.height as f64))
+            .into_iter()
+            .fold(0.0, f64::max);
+        let level = footprint
+            .max(1e-9)
+            .log2()
+            .clamp(0.0, (self.levels.len() - 1) as f64);
+
+        let lower = level.floor() as usize;
+        let fine = self.levels[lower].bilinear(coordinates);
+        if lower + 1 == self.levels.len() {
+            return fine;
+        }
+        let t = level - lower as f64;
+        fine * (1.0 - t) + self.levels[lower + 1].bilinear(coordinates) * t
+    }
+}
+
+/// Where textures are evaluated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mapping {
+    /// Texture coordinates of the mesh as (u, v, 0)
+    Uv,
+    /// Scene coordinates of the point, objects have no transforms of their own
+    Object,
+}
+
+/// Maps texture space points to pattern space: scales, then rotates and offsets them.
+pub struct TextureTransform {
+    pub scale: Vector3D,
+    /// Unit axis and angle in radians
+    pub rotation: (Vector3D, f64),
+    pub offset: Vector3D,
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            scale: Vector3D::from([1.0, 1.0, 1.0]),
+            rotation: (Vector3D::from([0.0, 1.0, 0.0]), 0.0),
+            offset: Vector3D::default(),
+        }
+    }
+}
+
+impl TextureTransform {
+    fn apply(&self, point: &Vector3D) -> Vector3D {
+        self.apply_linear(point) + &self.offset
+    }
+
+    /// Without the offset, for derivatives.
+    fn apply_linear(&self, vector: &Vector3D) -> Vector3D {
+        let (axis, angle) = &self.rotation;
+        vector.component_mul(&self.scale).rotate(axis, *angle)
+    }
+}
+
+pub enum Pattern {
+    Image(ImageTexture),
+    /// Unit cubes, or squares in texture coordinates, alternating between the colors
+    Checkerboard,
+    /// Fractional Brownian motion of Perlin noise
+    Noise {
+        octaves: usize,
+    },
+    /// Rings around the y axis, one per unit, distorted by noise
+    Wood {
+        octaves: usize,
+    },
+    /// Veins along the x axis, one per unit, distorted by turbulence
+    Marble {
+        octaves: usize,
+    },
+}
+
+fn checkerboard(point: &Vector3D) -> f64 {
+    // Points on axis aligned planes land on both sides of integer coordinates
+    let cells: i64 = [point.x, point.y, point.z]
+        .map(|coordinate| (coordinate + 1e-6).floor() as i64)
+        .iter()
+        .sum();
+    cells.rem_euclid(2) as f64
+}
+
+fn wood(point: &Vector3D, octaves: usize) -> f64 {
+    let radius = point.x.hypot(point.z) + 0.3 * fbm(point, octaves);
+    radius.rem_euclid(1.0)
+}
+
+fn marble(point: &Vector3D, octaves: usize) -> f64 {
+    let phase = 2.0 * std::f64::consts::PI * point.x + 5.0 * turbulence(point, octaves);
+    0.5 + 0.5 * phase.sin()
+}
+
+/// Derivatives of a surface point along the pixel x and y.
+#[derive(Clone)]
+pub struct SurfaceDifferentials {
+    pub position: [Vector3D; 2],
+    /// Zero without texture coordinates
+    pub coordinates: [[f64; 2]; 2],
+}
+
+/// Where a texture is looked up.
+pub struct SurfacePoint {
+    pub position: Vector3D,
+    /// Texture coordinates, if the mesh has them
+    pub coordinates: Option<[f64; 2]>,
+    /// Known for points seen from the camera directly or through specular bounces
+    pub differentials: Option<SurfaceDifferentials>,
+}
+
+/// Pattern that drives a material parameter, procedural or from an image.
+pub struct Texture {
+    pub pattern: Pattern,
+    /// Procedural patterns blend between these colors
+    pub colors: [Vector3D; 2],
+    pub mapping: Mapping,
+    pub transform: TextureTransform,
+}
+
+impl Texture {
+    /// Color at a surface point. Meshes without texture coordinates look up the texture
+    /// at their origin. Images are filtered over the footprint of the point, if it is known.
+    pub fn evaluate(&self, surface: &SurfacePoint) -> Vector3D {
+        let point = match (self.mapping, surface.coordinates) {
+            (Mapping::Object, _) => surface.position.clone(),
+            (Mapping::Uv, Some([u, v])) => Vector3D::from([u, v, 0.0]),
+            (Mapping::Uv, None) => Vector3D::default(),
+        };
+        let point = self.transform.apply(&point);
+
+        let t = match self.pattern {
+            Pattern::Image(ref image) => {
+                let Some(ref differentials) = surface.differentials else {
+                    return image.lookup([point.x, point.y]);
+                };
+                let derivatives = [0, 1].map(|i| {
+                    let derivative = match self.mapping {
+                        Mapping::Object => differentials.position[i].clone(),
+                        Mapping::Uv => {
+                            let [du, dv] = differentials.coordinates[i];
+                            Vector3D::from([du, dv, 0.0])
+                        }
+                    };
+                    let derivative = self.transform.apply_linear(&derivative);
+                    [derivative.x, derivative.y]
+                });
+                return image.filtered([point.x, point.y], derivatives);
+            }
+            Pattern::Checkerboard => checkerboard(&point),
+            Pattern::Noise { octaves } => (0.5 + 0.5 * fbm(&point, octaves)).clamp(0.0, 1.0),
+            Pattern::Wood { octaves } => wood(&point, octaves),
+            Pattern::Marble { octaves } => marble(&point, octaves),
+        };
+        &self.colors[0] * (1.0 - t) + &self.colors[1] * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn color_images_are_decoded_from_srgb() {
+        let path = std::env::temp_dir().join(format!("srgb_{}.png", std::process::id()));
+        RgbImage::from_pixel(1, 1, Rgb([0, 128, 255]))
+            .save(&path)
+            .unwrap();
+        let color = ImageTexture::open_srgb(&path).unwrap().lookup([0.5, 0.5]);
+        let stored = ImageTexture::open(&path).unwrap().lookup([0.5, 0.5]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(color.x, 0.0);
+        assert!((color.y - 0.2158).abs() < 1e-3);
+        assert!((color.z - 1.0).abs() < 1e-6);
+        assert!((stored.y - 128.0 / 255.0).abs() < 1e-6);
+    }
+}
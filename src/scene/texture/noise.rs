@@ -0,0 +1,91 @@
+use crate::geometry::vector::Vector3D;
+
+/// Octaves past this are finer than colors can show, and their frequencies would overflow.
+pub static MAX_OCTAVES: usize = 16;
+
+/// Gradients along the edges of a cube, as in improved Perlin noise.
+static GRADIENTS: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// Integer hash of a lattice point, standing in for the permutation table.
+fn hash(x: i64, y: i64, z: i64) -> usize {
+    let mut hash = (x as u64).wrapping_mul(0x8da6_b343)
+        ^ (y as u64).wrapping_mul(0xd816_3841)
+        ^ (z as u64).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1_e995);
+    (hash ^ (hash >> 15)) as usize
+}
+
+fn gradient_dot(corner: [i64; 3], offset: [f64; 3]) -> f64 {
+    let [gx, gy, gz] = GRADIENTS[hash(corner[0], corner[1], corner[2]) % GRADIENTS.len()];
+    gx * offset[0] + gy * offset[1] + gz * offset[2]
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Improved Perlin noise (Perlin 2002), roughly in [-1, 1] and zero at lattice points.
+pub fn perlin(point: &Vector3D) -> f64 {
+    let cell = [point.x.floor(), point.y.floor(), point.z.floor()];
+    let [x, y, z] = cell.map(|coordinate| coordinate as i64);
+    let [dx, dy, dz] = [point.x - cell[0], point.y - cell[1], point.z - cell[2]];
+
+    let corner = |i: i64, j: i64, k: i64| {
+        gradient_dot(
+            [x + i, y + j, z + k],
+            [dx - i as f64, dy - j as f64, dz - k as f64],
+        )
+    };
+    let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+/// Fractional Brownian motion, octaves of noise with doubling frequency and halving amplitude.
+pub fn fbm(point: &Vector3D, octaves: usize) -> f64 {
+    (0..octaves)
+        .map(|octave| {
+            let frequency = (1 << octave) as f64;
+            perlin(&(point * frequency)) / frequency
+        })
+        .sum()
+}
+
+/// Like `fbm`, but sums absolute values, which gives sharp creases.
+pub fn turbulence(point: &Vector3D, octaves: usize) -> f64 {
+    (0..octaves)
+        .map(|octave| {
+            let frequency = (1 << octave) as f64;
+            perlin(&(point * frequency)).abs() / frequency
+        })
+        .sum()
+}